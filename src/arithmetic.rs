@@ -0,0 +1,548 @@
+use std::error::Error;
+
+use crate::session::Session;
+
+/*
+Evaluates arithmetic expressions, as used by $((...)), let and ((...)).
+All values are 64-bit signed integers, which wrap around on overflow.
+Operators and their precedence follow C, from lowest to highest:
+    ,                       sequence, results in the right value
+    = += -= *= /= %= <<= >>= &= ^= |=
+    ?:                      conditional
+    ||  &&                  logical, short-circuiting
+    |  ^  &                 bitwise
+    ==  !=  <  >  <=  >=    comparison, 1 if true and 0 otherwise
+    <<  >>                  shifts
+    +  -  *  /  %
+    **                      exponentiation
+    + - ! ~ ++ --           prefix operators, followed by postfix ++ and --
+Variables are referred to by their name. Unset or empty ones count as 0,
+while values which aren't numbers are evaluated as expressions themselves.
+*/
+pub fn evaluate(expression: &str, session: &mut Session) -> Result<i64, Box<dyn Error>> {
+    evaluate_nested(expression, session, 0)
+}
+
+//How often the value of a variable may refer to another one (e.g. a=b, b=a)
+const MAX_DEPTH: usize = 64;
+
+fn evaluate_nested(
+    expression: &str,
+    session: &mut Session,
+    depth: usize,
+) -> Result<i64, Box<dyn Error>> {
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_comma()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(format!(
+            "Syntax error in arithmetic expression '{}' near {}",
+            expression.trim(),
+            token
+        )
+        .into());
+    }
+    Evaluator { session, depth }.evaluate(&expr)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "'{}'", n),
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Op(op) => write!(f, "'{}'", op),
+        }
+    }
+}
+
+//Every operator, longer ones first so that e.g. "<<=" isn't read as "<" and "<="
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "!", "~",
+    "?", ":", "=", "(", ")", ",",
+];
+
+const ASSIGNMENT_OPERATORS: [&str; 11] = [
+    "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            //Numbers may contain letters as well, e.g. 0xff or 16#ff
+            let length = chars[pos..]
+                .iter()
+                .take_while(|d| {
+                    d.is_ascii_alphanumeric() || **d == '_' || (**d == '#' && c.is_ascii_digit())
+                })
+                .count();
+            let word: String = chars[pos..pos + length].iter().collect();
+            pos += length;
+            tokens.push(if c.is_ascii_digit() {
+                Token::Number(parse_number(&word)?)
+            } else {
+                Token::Name(word)
+            });
+        } else {
+            let rest: String = chars[pos..].iter().take(3).collect();
+            let op = OPERATORS
+                .into_iter()
+                .find(|op| rest.starts_with(op))
+                .ok_or(format!(
+                    "Invalid character '{}' in arithmetic expression",
+                    c
+                ))?;
+            pos += op.len();
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+//Parses an integer constant: decimal, octal (leading 0), hexadecimal (0x) or base#digits (e.g. 2#101)
+fn parse_number(text: &str) -> Result<i64, Box<dyn Error>> {
+    let (digits, base) = if let Some((base, digits)) = text.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base) if (2..=36).contains(&base) => (digits, base),
+            _ => return Err(format!("Invalid arithmetic base: {}", base).into()),
+        }
+    } else if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (digits, 16)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (&text[1..], 8)
+    } else {
+        (text, 10)
+    };
+    u64::from_str_radix(digits, base)
+        .map(|n| n as i64)
+        .map_err(|_| format!("Invalid number '{}' in arithmetic expression", text).into())
+}
+
+enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(String, &'static str, Box<Expr>),
+    Increment {
+        name: String,
+        amount: i64,
+        prefix: bool, //Whether the new value (++x) or the old one (x++) is the result
+    },
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize, //Index of the next token to be consumed
+}
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), Box<dyn Error>> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn unexpected(&self) -> Box<dyn Error> {
+        match self.tokens.get(self.pos) {
+            Some(token) => format!("Syntax error in arithmetic expression near {}", token).into(),
+            None => "Syntax error: arithmetic expression ends unexpectedly".into(),
+        }
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let mut expr = self.parse_assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(self.parse_assignment()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr, Box<dyn Error>> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if ASSIGNMENT_OPERATORS.contains(op) {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                let value = self.parse_assignment()?;
+                return Ok(Expr::Assign(name, op, Box::new(value)));
+            }
+        }
+        self.parse_conditional()
+    }
+
+    fn parse_conditional(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let condition = self.parse_binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then = self.parse_comma()?;
+        self.expect(":")?;
+        let otherwise = self.parse_assignment()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    //Precedence of binary operators, higher ones binding more tightly
+    fn binary_precedence(op: &str) -> Option<u8> {
+        Some(match op {
+            "||" => 0,
+            "&&" => 1,
+            "|" => 2,
+            "^" => 3,
+            "&" => 4,
+            "==" | "!=" => 5,
+            "<" | ">" | "<=" | ">=" => 6,
+            "<<" | ">>" => 7,
+            "+" | "-" => 8,
+            "*" | "/" | "%" => 9,
+            "**" => 10,
+            _ => return None,
+        })
+    }
+
+    //Parses binary operators with a precedence of at least min_precedence
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, Box<dyn Error>> {
+        let mut left = self.parse_unary()?;
+        while let Some(op) = self.peek_op() {
+            let Some(precedence) = Self::binary_precedence(op) else {
+                break;
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            self.pos += 1;
+            //** is right-associative, all others are left-associative
+            let next_min = if op == "**" {
+                precedence
+            } else {
+                precedence + 1
+            };
+            let right = self.parse_binary(next_min)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Box<dyn Error>> {
+        match self.peek_op() {
+            Some(op @ ("+" | "-" | "!" | "~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Name(name)) => {
+                        let name = name.clone();
+                        self.pos += 1;
+                        Ok(Expr::Increment {
+                            name,
+                            amount: if op == "++" { 1 } else { -1 },
+                            prefix: true,
+                        })
+                    }
+                    _ => Err(self.unexpected()),
+                }
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, Box<dyn Error>> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Name(name)) => match self.peek_op() {
+                Some(op @ ("++" | "--")) => {
+                    self.pos += 1;
+                    Ok(Expr::Increment {
+                        name,
+                        amount: if op == "++" { 1 } else { -1 },
+                        prefix: false,
+                    })
+                }
+                _ => Ok(Expr::Variable(name)),
+            },
+            Some(Token::Op("(")) => {
+                let expr = self.parse_comma()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected())
+            }
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    session: &'a mut Session,
+    depth: usize, //How many variables' values are being evaluated as expressions
+}
+
+impl Evaluator<'_> {
+    fn evaluate(&mut self, expr: &Expr) -> Result<i64, Box<dyn Error>> {
+        Ok(match expr {
+            Expr::Number(n) => *n,
+            Expr::Variable(name) => self.variable(name)?,
+            Expr::Unary(op, operand) => {
+                let value = self.evaluate(operand)?;
+                match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                }
+            }
+            //Operands which don't affect the result aren't evaluated, along with their side effects
+            Expr::Binary("&&", left, right) => {
+                (self.evaluate(left)? != 0 && self.evaluate(right)? != 0) as i64
+            }
+            Expr::Binary("||", left, right) => {
+                (self.evaluate(left)? != 0 || self.evaluate(right)? != 0) as i64
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                apply_binary(op, left, right)?
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                if self.evaluate(condition)? != 0 {
+                    self.evaluate(then)?
+                } else {
+                    self.evaluate(otherwise)?
+                }
+            }
+            Expr::Assign(name, op, value) => {
+                let value = self.evaluate(value)?;
+                let value = match op.strip_suffix('=') {
+                    Some("") | None => value,
+                    Some(binary_op) => apply_binary(binary_op, self.variable(name)?, value)?,
+                };
+                self.session.set_var(name, value.to_string());
+                value
+            }
+            Expr::Increment {
+                name,
+                amount,
+                prefix,
+            } => {
+                let old = self.variable(name)?;
+                let new = old.wrapping_add(*amount);
+                self.session.set_var(name, new.to_string());
+                if *prefix {
+                    new
+                } else {
+                    old
+                }
+            }
+        })
+    }
+
+    fn variable(&mut self, name: &str) -> Result<i64, Box<dyn Error>> {
+        let value = self.session.get_var(name).unwrap_or_default();
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(n) = value.parse::<i64>() {
+            return Ok(n);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("{}: expression recursion level exceeded", name).into());
+        }
+        evaluate_nested(value, self.session, self.depth + 1)
+    }
+}
+
+fn apply_binary(op: &str, left: i64, right: i64) -> Result<i64, Box<dyn Error>> {
+    Ok(match op {
+        "," => right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        ">" => (left > right) as i64,
+        "<=" => (left <= right) as i64,
+        ">=" => (left >= right) as i64,
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("Division by zero".into()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err("Exponent less than 0".into()),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        _ => return Err(format!("Unknown operator '{}'", op).into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> i64 {
+        evaluate(expression, &mut Session::new()).unwrap()
+    }
+
+    fn error(expression: &str) -> String {
+        evaluate(expression, &mut Session::new())
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn follows_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("10 - 4 - 3"), 3);
+        assert_eq!(eval("1 << 2 + 1"), 8);
+        assert_eq!(eval("1 | 2 ^ 3 & 6"), 1);
+        assert_eq!(eval("1 < 2 == 1"), 1);
+        assert_eq!(eval("-2 ** 2"), 4);
+        assert_eq!(eval("!0 + ~0"), 0);
+        assert_eq!(eval("1, 2, 3"), 3);
+    }
+
+    #[test]
+    fn exponentiation_is_right_associative() {
+        assert_eq!(eval("2 ** 3 ** 2"), 512);
+        assert_eq!(eval("2 * 3 ** 2"), 18);
+        assert_eq!(error("2 ** -1"), "Exponent less than 0");
+    }
+
+    #[test]
+    fn evaluates_conditionals_lazily() {
+        assert_eq!(eval("1 ? 2 : 3"), 2);
+        assert_eq!(eval("0 ? 2 : 0 ? 3 : 4"), 4);
+        //Neither the branch not taken nor the right side of && and || is evaluated
+        let mut session = Session::new();
+        evaluate("0 ? (x = 1) : (y = 2)", &mut session).unwrap();
+        evaluate("0 && (z = 1), 1 || (z = 2)", &mut session).unwrap();
+        assert_eq!(session.get_var("x"), None);
+        assert_eq!(session.get_var("y").as_deref(), Some("2"));
+        assert_eq!(session.get_var("z"), None);
+        assert_eq!(evaluate("1 / 0 || 1", &mut session).ok(), None);
+        assert_eq!(evaluate("0 && 1 / 0", &mut session).unwrap(), 0);
+    }
+
+    #[test]
+    fn assigns_variables() {
+        let mut session = Session::new();
+        assert_eq!(evaluate("a = b = 5", &mut session).unwrap(), 5);
+        assert_eq!(session.get_var("a").as_deref(), Some("5"));
+        assert_eq!(session.get_var("b").as_deref(), Some("5"));
+        for (expression, value) in [
+            ("a += 3", 8),
+            ("a -= 1", 7),
+            ("a *= 2", 14),
+            ("a /= 4", 3),
+            ("a %= 2", 1),
+            ("a <<= 4", 16),
+            ("a >>= 1", 8),
+            ("a |= 3", 11),
+            ("a &= 6", 2),
+            ("a ^= 7", 5),
+        ] {
+            assert_eq!(
+                evaluate(expression, &mut session).unwrap(),
+                value,
+                "{}",
+                expression
+            );
+        }
+        assert_eq!(session.get_var("a").as_deref(), Some("5"));
+        assert_eq!(evaluate("a++ + ++a", &mut session).unwrap(), 12);
+        assert_eq!(evaluate("--a", &mut session).unwrap(), 6);
+        assert!(evaluate("1 = 2", &mut session).is_err());
+    }
+
+    #[test]
+    fn reads_variables() {
+        let mut session = Session::new();
+        session.set_var("n", "4".to_string());
+        session.set_var("expr", "n * 2".to_string());
+        session.set_var("empty", String::new());
+        assert_eq!(evaluate("n + 1", &mut session).unwrap(), 5);
+        assert_eq!(evaluate("expr + 1", &mut session).unwrap(), 9);
+        assert_eq!(evaluate("empty + unset", &mut session).unwrap(), 0);
+        //Variables referring to each other endlessly fail rather than overflow the stack
+        session.set_var("p", "q".to_string());
+        session.set_var("q", "p".to_string());
+        assert!(evaluate("p", &mut session).is_err());
+    }
+
+    #[test]
+    fn reads_numbers_in_other_bases() {
+        assert_eq!(eval("0x1F"), 31);
+        assert_eq!(eval("0X10"), 16);
+        assert_eq!(eval("010"), 8);
+        assert_eq!(eval("0"), 0);
+        assert_eq!(eval("2#1010"), 10);
+        assert_eq!(eval("16#ff"), 255);
+        assert_eq!(eval("36#z"), 35);
+        assert!(error("08").starts_with("Invalid number '08'"));
+        assert!(error("2#102").starts_with("Invalid number"));
+        assert_eq!(error("1#0"), "Invalid arithmetic base: 1");
+        assert_eq!(error("37#0"), "Invalid arithmetic base: 37");
+    }
+
+    #[test]
+    fn fails_on_division_by_zero() {
+        assert_eq!(error("1 / 0"), "Division by zero");
+        assert_eq!(error("1 % 0"), "Division by zero");
+        assert_eq!(error("x /= 0"), "Division by zero");
+        //Overflowing values wrap around instead
+        assert_eq!(eval("-9223372036854775807 - 2"), i64::MAX);
+        assert_eq!(eval("(-9223372036854775807 - 1) / -1"), i64::MIN);
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert_eq!(eval(""), 0);
+        assert!(error("1 +").contains("arithmetic"));
+        assert!(error("(1 + 2").contains("arithmetic"));
+        assert!(error("1 2").contains("near '2'"));
+        assert!(evaluate("1 ? 2", &mut Session::new()).is_err());
+    }
+}
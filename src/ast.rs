@@ -0,0 +1,465 @@
+use std::{fmt, rc::Rc};
+
+//A piece of a word, as written by the user. Quoting is preserved until expansion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    Literal(String),             //Unquoted text
+    SingleQuoted(String),        //Text within '...', taken literally
+    DoubleQuoted(Vec<WordPart>), //Text within "...", may contain substitutions
+    Substitution(String),        //Source of a command substitution (e.g. whoami for $(whoami))
+    LegacySubstitution(String), //Same, but written as ${whoami}. Only with 'set -o legacy_substitution'.
+    Parameter(Parameter),       //$NAME or ${NAME}, replaced by the variable's value
+    Arithmetic(Vec<WordPart>),  //Expression of $((...)), expanded like "..." before it's evaluated
+}
+
+//A reference to a shell variable or a special parameter (e.g. $HOME, ${PATH}, $?, $1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub braced: bool, //Written as ${NAME}, which may also be a legacy substitution (e.g. ${whoami})
+    pub index: Option<Word>, //Subscript of an array element (e.g. 1 in ${PIPESTATUS[1]}), or @ for all of them
+    pub operation: Option<ParameterOperation>, //E.g. :-default in ${NAME:-default}
+}
+
+/*
+Modifies the value a parameter expands to. For the first four, check_null decides
+whether an empty value is treated like an unset one (written with ':', e.g. ${NAME:-word}).
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterOperation {
+    UseDefault {
+        word: Word,
+        check_null: bool,
+    }, //${NAME:-word}, word if NAME is unset
+    AssignDefault {
+        word: Word,
+        check_null: bool,
+    }, //${NAME:=word}, same, but also assigns word to NAME
+    ErrorIfUnset {
+        word: Word,
+        check_null: bool,
+    }, //${NAME:?word}, fails with word as message if NAME is unset
+    UseAlternative {
+        word: Word,
+        check_null: bool,
+    }, //${NAME:+word}, word if NAME is set, nothing otherwise
+    Length, //${#NAME}, number of characters
+    RemovePrefix {
+        pattern: Word,
+        longest: bool,
+    }, //${NAME#pattern} and ${NAME##pattern}
+    RemoveSuffix {
+        pattern: Word,
+        longest: bool,
+    }, //${NAME%pattern} and ${NAME%%pattern}
+    Replace {
+        pattern: Word,
+        replacement: Word,
+        all: bool,
+    }, //${NAME/pattern/replacement}, // for all matches
+    Substring {
+        offset: Word,
+        length: Option<Word>,
+    }, //${NAME:offset:length}
+}
+
+//A single word of a command, made up of one or more adjacent parts (e.g. abc"def"${ghi})
+pub type Word = Vec<WordPart>;
+
+//Specifies how to open the file a redirection points to
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedirectionKind {
+    Overwrite,  //>
+    Append,     //>>
+    Input,      //<, read stdin from file
+    HereString, //<<<, read stdin from the word itself
+    HereDoc,    //<< or <<-, read stdin from the here-document's body (the target)
+    Duplicate, //>& or <&, make fd a copy of the descriptor named by the target (or close it for '-')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirection {
+    pub fd: u32, //File descriptor being redirected (e.g. 2 for 2>file)
+    pub kind: RedirectionKind,
+    pub target: Word, //Filename (or here-string), expanded right before the command is run
+}
+
+//NAME=value, either on its own or in front of a command
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+//A program (or builtin) with its arguments and redirections
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>, //Set for the command only, or for the session if there's no command
+    pub words: Vec<Word>,             //Program followed by its arguments
+    pub redirections: Vec<Redirection>,
+}
+
+//A command made up of other commands
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompoundCommand {
+    Subshell(List), //( list ), run in a copy of the session, so that changes (e.g. cd) don't leak back
+    Group(List),    //{ list; }, run in the current session
+    If {
+        branches: Vec<(List, List)>, //Condition and body of the if and of every elif
+        else_body: Option<List>,
+    }, //if list; then list; elif list; then list; else list; fi
+    While {
+        condition: List,
+        body: List,
+        until: bool, //Loop as long as the condition fails rather than succeeds
+    }, //while list; do list; done and until list; do list; done
+    For {
+        variable: String,
+        words: Option<Vec<Word>>, //Without 'in', the loop goes through the positional parameters
+        body: List,
+    }, //for NAME in word...; do list; done
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+    }, //case word in pattern|pattern) list;; ... esac
+}
+
+//A branch of a case command, whose body is run if the word matches one of the patterns
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+//name() compound-command, which defines a function running the compound command when called
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub body: CompoundCommand,
+    pub redirections: Vec<Redirection>, //Applied to the body on every call
+}
+
+//A stage of a pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirection>), //The redirections apply to every command within
+    Function(Rc<FunctionDefinition>),            //Shared with the session once it's defined
+}
+
+//One or more commands, each one's stdout connected to the stdin of the next one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    pub negated: bool, //Preceded by !, the pipeline succeeds if its last command fails and vice versa
+}
+
+//Decides whether the pipeline following it is run, based on the exit status of the previous one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Connector {
+    And, //&&, run if the previous pipeline succeeded
+    Or,  //||, run if the previous pipeline failed
+}
+
+//Pipelines chained with && and ||
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOrList {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    pub background: bool, //Terminated by '&', run without waiting for it to finish
+}
+
+//AND-OR lists separated by ';', '&' or newlines, executed one after another
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    pub and_or_lists: Vec<AndOrList>,
+}
+
+/*
+The implementations below turn the syntax tree back into source text,
+e.g. to show the command of a job. Spacing and quoting may differ
+from what the user typed, but the meaning stays the same.
+*/
+
+fn write_word(f: &mut fmt::Formatter, word: &Word) -> fmt::Result {
+    word.iter().try_for_each(|part| write!(f, "{}", part))
+}
+
+impl fmt::Display for WordPart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WordPart::Literal(text) => write!(f, "{}", text),
+            WordPart::SingleQuoted(text) => write!(f, "'{}'", text.replace('\'', "'\\''")),
+            WordPart::DoubleQuoted(parts) => {
+                write!(f, "\"")?;
+                write_word(f, parts)?;
+                write!(f, "\"")
+            }
+            WordPart::Substitution(source) => write!(f, "$({})", source),
+            WordPart::LegacySubstitution(source) => write!(f, "${{{}}}", source),
+            WordPart::Parameter(parameter) => write!(f, "{}", parameter),
+            WordPart::Arithmetic(parts) => {
+                write!(f, "$((")?;
+                write_word(f, parts)?;
+                write!(f, "))")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.braced {
+            return write!(f, "${}", self.name);
+        }
+        write!(f, "${{")?;
+        if let Some(ParameterOperation::Length) = self.operation {
+            write!(f, "#")?;
+        }
+        write!(f, "{}", self.name)?;
+        if let Some(index) = &self.index {
+            write!(f, "[")?;
+            write_word(f, index)?;
+            write!(f, "]")?;
+        }
+        if let Some(operation) = &self.operation {
+            write!(f, "{}", operation)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl fmt::Display for ParameterOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        //The operator written between the name and the word, e.g. ":-" or "##"
+        let (operator, word) = match self {
+            ParameterOperation::UseDefault { word, check_null } => (("-", *check_null), word),
+            ParameterOperation::AssignDefault { word, check_null } => (("=", *check_null), word),
+            ParameterOperation::ErrorIfUnset { word, check_null } => (("?", *check_null), word),
+            ParameterOperation::UseAlternative { word, check_null } => (("+", *check_null), word),
+            ParameterOperation::RemovePrefix { pattern, longest } => {
+                ((if *longest { "##" } else { "#" }, false), pattern)
+            }
+            ParameterOperation::RemoveSuffix { pattern, longest } => {
+                ((if *longest { "%%" } else { "%" }, false), pattern)
+            }
+            ParameterOperation::Replace {
+                pattern,
+                replacement,
+                all,
+            } => {
+                write!(f, "{}", if *all { "//" } else { "/" })?;
+                write_word(f, pattern)?;
+                write!(f, "/")?;
+                return write_word(f, replacement);
+            }
+            ParameterOperation::Substring { offset, length } => {
+                write!(f, ":")?;
+                write_word(f, offset)?;
+                if let Some(length) = length {
+                    write!(f, ":")?;
+                    write_word(f, length)?;
+                }
+                return Ok(());
+            }
+            //Written in front of the name, see Parameter
+            ParameterOperation::Length => return Ok(()),
+        };
+        match operator {
+            (symbol, true) => write!(f, ":{}", symbol)?,
+            (symbol, false) => write!(f, "{}", symbol)?,
+        }
+        write_word(f, word)
+    }
+}
+
+impl fmt::Display for Redirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (operator, default_fd) = match self.kind {
+            RedirectionKind::Overwrite => (">", 1),
+            RedirectionKind::Append => (">>", 1),
+            RedirectionKind::Input => ("<", 0),
+            RedirectionKind::HereString => ("<<<", 0),
+            //The delimiter isn't kept, so the body is left out
+            RedirectionKind::HereDoc => return write!(f, "<<..."),
+            RedirectionKind::Duplicate if self.fd == 0 => ("<&", 0),
+            RedirectionKind::Duplicate => (">&", 1),
+        };
+        if self.fd != default_fd {
+            write!(f, "{}", self.fd)?;
+        }
+        write!(f, "{}", operator)?;
+        write_word(f, &self.target)
+    }
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separator = "";
+        for assignment in &self.assignments {
+            write!(f, "{}{}=", separator, assignment.name)?;
+            write_word(f, &assignment.value)?;
+            separator = " ";
+        }
+        for word in &self.words {
+            write!(f, "{}", separator)?;
+            write_word(f, word)?;
+            separator = " ";
+        }
+        for redirection in &self.redirections {
+            write!(f, "{}{}", separator, redirection)?;
+            separator = " ";
+        }
+        Ok(())
+    }
+}
+
+//Writes a list followed by the ';' which ends it in front of a reserved word, unless it ends with '&'
+fn write_terminated(f: &mut fmt::Formatter, list: &List) -> fmt::Result {
+    match list.and_or_lists.last() {
+        Some(and_or) if and_or.background => write!(f, "{} ", list),
+        _ => write!(f, "{}; ", list),
+    }
+}
+
+impl fmt::Display for CompoundCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompoundCommand::Subshell(body) => write!(f, "({})", body),
+            CompoundCommand::Group(body) => {
+                write!(f, "{{ ")?;
+                write_terminated(f, body)?;
+                write!(f, "}}")
+            }
+            CompoundCommand::If {
+                branches,
+                else_body,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    write!(f, "{} ", if i == 0 { "if" } else { "elif" })?;
+                    write_terminated(f, condition)?;
+                    write!(f, "then ")?;
+                    write_terminated(f, body)?;
+                }
+                if let Some(body) = else_body {
+                    write!(f, "else ")?;
+                    write_terminated(f, body)?;
+                }
+                write!(f, "fi")
+            }
+            CompoundCommand::While {
+                condition,
+                body,
+                until,
+            } => {
+                write!(f, "{} ", if *until { "until" } else { "while" })?;
+                write_terminated(f, condition)?;
+                write!(f, "do ")?;
+                write_terminated(f, body)?;
+                write!(f, "done")
+            }
+            CompoundCommand::For {
+                variable,
+                words,
+                body,
+            } => {
+                write!(f, "for {}", variable)?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    for word in words {
+                        write!(f, " ")?;
+                        write_word(f, word)?;
+                    }
+                }
+                write!(f, "; do ")?;
+                write_terminated(f, body)?;
+                write!(f, "done")
+            }
+            CompoundCommand::Case { word, items } => {
+                write!(f, "case ")?;
+                write_word(f, word)?;
+                write!(f, " in ")?;
+                for item in items {
+                    for (i, pattern) in item.patterns.iter().enumerate() {
+                        if i != 0 {
+                            write!(f, "|")?;
+                        }
+                        write_word(f, pattern)?;
+                    }
+                    write!(f, ") ")?;
+                    if !item.body.and_or_lists.is_empty() {
+                        write!(f, "{} ", item.body)?;
+                    }
+                    write!(f, ";; ")?;
+                }
+                write!(f, "esac")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Simple(command) => write!(f, "{}", command),
+            Command::Compound(compound, redirections) => {
+                write!(f, "{}", compound)?;
+                for redirection in redirections {
+                    write!(f, " {}", redirection)?;
+                }
+                Ok(())
+            }
+            Command::Function(function) => {
+                write!(f, "{}() {}", function.name, function.body)?;
+                for redirection in &function.redirections {
+                    write!(f, " {}", redirection)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for (i, command) in self.commands.iter().enumerate() {
+            if i != 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOrList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            match connector {
+                Connector::And => write!(f, " && {}", pipeline)?,
+                Connector::Or => write!(f, " || {}", pipeline)?,
+            }
+        }
+        if self.background {
+            write!(f, " &")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = self.and_or_lists.len();
+        for (i, and_or) in self.and_or_lists.iter().enumerate() {
+            write!(f, "{}", and_or)?;
+            if i != count - 1 {
+                write!(f, "{} ", if and_or.background { "" } else { ";" })?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    os::{
+        fd::{AsRawFd, BorrowedFd, IntoRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::Command,
+};
+
+/*
+The file descriptors a command is run with, built from its pipes and redirections.
+Descriptors missing from the table are inherited from the shell itself.
+*/
+pub struct FdTable {
+    fds: HashMap<u32, Option<OwnedFd>>, //None, if the descriptor was closed (e.g. 2>&-)
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self {
+            fds: HashMap::new(),
+        }
+    }
+
+    //Returns a table with new handles to the same targets
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let mut fds = HashMap::new();
+        for (fd, target) in &self.fds {
+            let target = match target {
+                Some(target) => Some(target.try_clone()?),
+                None => None,
+            };
+            fds.insert(*fd, target);
+        }
+        Ok(Self { fds })
+    }
+
+    //Makes fd refer to target (a file, a pipe etc.)
+    pub fn set(&mut self, fd: u32, target: impl Into<OwnedFd>) {
+        self.fds.insert(fd, Some(target.into()));
+    }
+
+    pub fn close(&mut self, fd: u32) {
+        self.fds.insert(fd, None);
+    }
+
+    //Makes fd a copy of source, as in 2>&1
+    pub fn duplicate(&mut self, fd: u32, source: u32) -> io::Result<()> {
+        match self.get(source)? {
+            Some(target) => self.set(fd, target),
+            None => return Err(io::Error::from_raw_os_error(libc::EBADF)),
+        }
+        Ok(())
+    }
+
+    //Whether fd is set up differently from the shell's own descriptor
+    pub fn is_redirected(&self, fd: u32) -> bool {
+        self.fds.contains_key(&fd)
+    }
+
+    /*
+    Returns a new handle to whatever fd refers to, or None if it has been closed.
+    Descriptors which weren't touched are duplicated from the shell.
+    */
+    pub fn get(&self, fd: u32) -> io::Result<Option<OwnedFd>> {
+        match self.fds.get(&fd) {
+            Some(Some(target)) => Ok(Some(target.try_clone()?)),
+            Some(None) => Ok(None),
+            None => {
+                //Safety: the descriptor is only borrowed for as long as it takes to duplicate it.
+                //Duplicating fails with EBADF, if the shell doesn't have it open.
+                let borrowed = unsafe { BorrowedFd::borrow_raw(fd as RawFd) };
+                Ok(Some(borrowed.try_clone_to_owned()?))
+            }
+        }
+    }
+
+    //Same as get(), but as a File for builtins to write to
+    pub fn writer(&self, fd: u32) -> io::Result<Option<File>> {
+        Ok(self.get(fd)?.map(File::from))
+    }
+
+    /*
+    Sets up the process to be spawned by command with the descriptors of the table.
+    This happens in the child process right before the program is executed.
+    */
+    pub fn apply(&self, command: &mut Command) {
+        let mapping = self.mapping();
+        if mapping.is_empty() {
+            return;
+        }
+        let mut moved: Vec<RawFd> = vec![-1; mapping.len()];
+
+        //Safety: only async-signal-safe functions are called and nothing is allocated.
+        unsafe {
+            command.pre_exec(move || redirect(&mapping, &mut moved));
+        }
+    }
+
+    /*
+    Sets up the descriptors of the shell process itself according to the table.
+    Used by subshells, which run in a forked copy of the shell.
+    */
+    pub fn install(self) -> io::Result<()> {
+        let mapping = self.mapping();
+        let mut moved: Vec<RawFd> = vec![-1; mapping.len()];
+        //Safety: descriptors are only duplicated and closed, the table's own ones are kept track of below
+        unsafe {
+            redirect(&mapping, &mut moved)?;
+            for fd in moved.into_iter().filter(|fd| *fd >= 0) {
+                libc::close(fd);
+            }
+        }
+        //A target which happens to have the number of a descriptor it was set up as must stay open
+        for target in self.fds.into_values().flatten() {
+            if mapping.iter().any(|(fd, _)| *fd == target.as_raw_fd()) {
+                let _ = target.into_raw_fd();
+            }
+        }
+        Ok(())
+    }
+
+    //(descriptor to set up, descriptor of the shell it should refer to or -1 to close it)
+    fn mapping(&self) -> Vec<(RawFd, RawFd)> {
+        self.fds
+            .iter()
+            .map(|(fd, target)| {
+                let source = target.as_ref().map_or(-1, |t| t.as_raw_fd());
+                (*fd as RawFd, source)
+            })
+            .collect()
+    }
+}
+
+/*
+Makes each descriptor of mapping a copy of its source, or closes it. Sources are moved
+out of the way first (into moved), so that setting up one descriptor can't clobber
+the source of another one (e.g. 3>&1 with the table's 1 being fd 3).
+Safety: must only be called with valid descriptors, and doesn't allocate,
+so that it can be run between fork and exec.
+*/
+unsafe fn redirect(mapping: &[(RawFd, RawFd)], moved: &mut [RawFd]) -> io::Result<()> {
+    let lowest_free = mapping
+        .iter()
+        .map(|(fd, src)| *fd.max(src))
+        .max()
+        .unwrap_or(0)
+        + 1;
+    for ((_, source), moved) in mapping.iter().zip(moved.iter_mut()) {
+        if *source >= 0 {
+            *moved = libc::fcntl(*source, libc::F_DUPFD_CLOEXEC, lowest_free);
+            if *moved < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    for ((fd, _), moved) in mapping.iter().zip(moved.iter()) {
+        if *moved >= 0 {
+            if libc::dup2(*moved, *fd) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        } else {
+            libc::close(*fd);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env, fs,
+        io::{Read, Write},
+        path::PathBuf,
+        process,
+    };
+
+    //A file of its own for each test, removed once the test is done
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(env::temp_dir().join(format!("my_shell_fd_{}_{}", name, process::id())))
+        }
+
+        fn create(&self) -> File {
+            File::create(&self.0).unwrap()
+        }
+
+        fn contents(&self) -> String {
+            fs::read_to_string(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    //Runs script with sh using the descriptors of fds and returns what it wrote to stdout
+    fn run(script: &str, fds: &mut FdTable) -> String {
+        let (mut reader, writer) = os_pipe::pipe().unwrap();
+        if !fds.is_redirected(1) {
+            fds.set(1, writer);
+        } else {
+            drop(writer);
+        }
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        fds.apply(&mut command);
+        let mut child = command.spawn().unwrap();
+        //The table's handles to the pipe must be gone for reading to end
+        *fds = FdTable::new();
+        drop(command);
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        child.wait().unwrap();
+        output
+    }
+
+    #[test]
+    fn applies_redirections_in_order() {
+        let file = TempFile::new("order");
+        //2>&1 >file: stderr goes where stdout went before, i.e. the pipe
+        let (mut reader, writer) = os_pipe::pipe().unwrap();
+        let mut fds = FdTable::new();
+        fds.set(1, writer);
+        fds.duplicate(2, 1).unwrap();
+        fds.set(1, file.create());
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2"]);
+        fds.apply(&mut command);
+        let mut child = command.spawn().unwrap();
+        drop((fds, command));
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        child.wait().unwrap();
+        assert_eq!(output, "err\n");
+        assert_eq!(file.contents(), "out\n");
+
+        //>file 2>&1: both go to the file
+        let mut fds = FdTable::new();
+        fds.set(1, file.create());
+        fds.duplicate(2, 1).unwrap();
+        assert_eq!(run("echo out; echo err >&2", &mut fds), "");
+        assert_eq!(file.contents(), "out\nerr\n");
+    }
+
+    #[test]
+    fn closes_descriptors() {
+        let mut fds = FdTable::new();
+        fds.close(2);
+        assert!(fds.is_redirected(2));
+        assert!(fds.get(2).unwrap().is_none());
+        assert!(fds.writer(2).unwrap().is_none());
+        //Duplicating a closed descriptor fails like in other shells
+        let err = fds.duplicate(3, 2).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+        //Writing to the closed stderr fails within the program
+        let output = run("echo err >&2 || echo failed", &mut fds);
+        assert_eq!(output, "failed\n");
+    }
+
+    #[test]
+    fn sources_are_not_clobbered() {
+        //fd 1 refers to a file whose descriptor has the number of another redirected one
+        let first = TempFile::new("clobber_first");
+        let second = TempFile::new("clobber_second");
+        let target = first.create();
+        let number = target.as_raw_fd() as u32;
+        let mut fds = FdTable::new();
+        fds.set(1, target);
+        fds.set(number, second.create());
+        let script = format!("echo one; echo two >&{}", number);
+        assert_eq!(run(&script, &mut fds), "");
+        assert_eq!(first.contents(), "one\n");
+        assert_eq!(second.contents(), "two\n");
+    }
+
+    #[test]
+    fn clones_share_targets() {
+        let (mut reader, writer) = os_pipe::pipe().unwrap();
+        let mut fds = FdTable::new();
+        fds.set(1, writer);
+        let clone = fds.try_clone().unwrap();
+        clone.writer(1).unwrap().unwrap().write_all(b"a").unwrap();
+        fds.writer(1).unwrap().unwrap().write_all(b"b").unwrap();
+        drop((fds, clone));
+        let mut output = String::new();
+        reader.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "ab");
+    }
+}
@@ -0,0 +1,412 @@
+use std::fs;
+
+/*
+Matching of shell patterns, as used by parameter expansion (e.g. ${VAR%.txt})
+and pathname expansion (e.g. *.rs).
+    *       matches any string, including the empty one
+    **      matches any number of directories within a path, if globstar is enabled
+    ?       matches any single character
+    [...]   matches one of the enclosed characters or ranges (e.g. [a-z]),
+            [!...] or [^...] any character not enclosed. Classes like [[:digit:]] are supported.
+    \c      matches the character c literally
+*/
+
+//Characters which have a special meaning within patterns
+pub const SPECIAL_CHARS: [char; 5] = ['*', '?', '[', ']', '\\'];
+
+//Escapes all special characters of text, so that it only matches itself
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if SPECIAL_CHARS.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+//Removes the escaping backslashes from a pattern, leaving the text it matches literally
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+//Whether pattern contains any unescaped wildcards, i.e. whether it may match more than just itself
+pub fn has_wildcards(pattern: &str) -> bool {
+    let mut chars = pattern.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' => return true,
+            '[' if pattern[i..].contains(']') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+pub struct PathOptions {
+    pub dotglob: bool,  //Whether wildcards match names starting with '.'
+    pub globstar: bool, //Whether ** matches directories recursively
+}
+
+/*
+Returns the paths matching pattern in sorted order, which is relative to the current
+directory unless it starts with '/'. Wildcards don't match a leading '.' of a name,
+unless the pattern explicitly starts with one (e.g. .*) or dotglob is set.
+*/
+pub fn expand_path(pattern: &str, options: &PathOptions) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![String::from("/")], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut matched = Vec::new();
+        for path in &paths {
+            if component.is_empty() {
+                //Consecutive or trailing slashes, the latter only matching directories
+                if !path.is_empty() && fs::metadata(path).is_ok_and(|m| m.is_dir()) {
+                    matched.push(format!("{}/", path.trim_end_matches('/')));
+                }
+            } else if *component == "**" && options.globstar {
+                if !last {
+                    matched.push(path.clone());
+                }
+                walk(path, options, !last, &mut matched);
+            } else if !has_wildcards(component) {
+                matched.push(join(path, &unescape(component)));
+            } else {
+                let hidden =
+                    options.dotglob || component.starts_with('.') || component.starts_with("\\.");
+                for name in entries(path) {
+                    if (hidden || !name.starts_with('.')) && matches(component, &name) {
+                        matched.push(join(path, &name));
+                    }
+                }
+            }
+        }
+        paths = matched;
+    }
+    //Components without wildcards are taken as they are, so they may not exist
+    paths.retain(|path| !path.is_empty() && fs::symlink_metadata(path).is_ok());
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+//Names of the entries of the directory at path (the current one if it's empty)
+fn entries(path: &str) -> Vec<String> {
+    let dir = if path.is_empty() { "." } else { path };
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+//Collects everything below path for **, or just the directories if the pattern continues after it
+fn walk(path: &str, options: &PathOptions, only_dirs: bool, found: &mut Vec<String>) {
+    let dir = if path.is_empty() { "." } else { path };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') && !options.dotglob {
+            continue;
+        }
+        //Symbolic links to directories aren't followed, so that cycles don't lead to endless recursion
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        let entry_path = join(path, &name);
+        if is_dir || !only_dirs {
+            found.push(entry_path.clone());
+        }
+        if is_dir {
+            walk(&entry_path, options, only_dirs, found);
+        }
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() || path.ends_with('/') {
+        format!("{}{}", path, name)
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+//Whether pattern matches the entire text
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_chars(&pattern, &text)
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    //Position of the last '*' in the pattern and the position in text it currently matches up to,
+    //so that it can be made to match one more character if the rest of the pattern fails.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match match_bracket(&pattern[p..], text[t]) {
+                Some((true, length)) => Some(length),
+                Some((false, _)) => None,
+                //An unclosed bracket is an ordinary character
+                None if text[t] == '[' => Some(1),
+                None => None,
+            },
+            Some('\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == text[t] {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Some(c) if *c == text[t] => Some(1),
+            _ => None,
+        };
+        match (step, backtrack) {
+            (Some(length), _) => {
+                p += length;
+                t += 1;
+            }
+            (None, Some((star, matched_up_to))) => {
+                p = star + 1;
+                t = matched_up_to + 1;
+                backtrack = Some((star, t));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/*
+Matches c against the bracket expression at the start of pattern.
+Returns whether it matched and the length of the expression,
+or None if the bracket isn't closed.
+*/
+fn match_bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let current = *pattern.get(i)?;
+        if current == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+
+        //Character classes, e.g. [:alpha:]
+        if current == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                matched |= matches_class(&rest[..end], c);
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let mut low = current;
+        if low == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            low = pattern[i];
+        }
+        //Ranges, e.g. a-z
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|c| *c != ']') {
+            let high = pattern[i + 2];
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= low == c;
+            i += 1;
+        }
+    }
+}
+
+fn matches_class(class: &str, c: char) -> bool {
+    match class {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        "cntrl" => c.is_control(),
+        "print" => !c.is_control(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::PathBuf, process};
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rs.bak"));
+        assert!(matches("a*b*c", "abbbc"));
+        assert!(matches("a*b*c", "axbyc"));
+        assert!(!matches("a*b*c", "axbyd"));
+        assert!(matches("?", "x"));
+        assert!(!matches("?", ""));
+        assert!(!matches("?", "xy"));
+        assert!(matches("**", ""));
+        assert!(matches("", ""));
+        assert!(!matches("", "x"));
+    }
+
+    #[test]
+    fn matches_brackets() {
+        assert!(matches("[abc]", "b"));
+        assert!(!matches("[abc]", "d"));
+        assert!(matches("file[0-9].txt", "file7.txt"));
+        assert!(!matches("file[0-9].txt", "filex.txt"));
+        assert!(matches("[!a-c]", "d"));
+        assert!(!matches("[^a-c]", "b"));
+        //A ']' right after the opening bracket is one of the characters
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a-]", "-"));
+        //An unclosed bracket matches itself
+        assert!(matches("[abc", "[abc"));
+        assert!(!matches("[abc", "a"));
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(matches("[[:digit:]]*", "1abc"));
+        assert!(!matches("[[:digit:]]*", "abc"));
+        assert!(matches("[[:upper:][:digit:]]", "Q"));
+        assert!(matches("[![:space:]]", "x"));
+        assert!(!matches("[![:space:]]", " "));
+        assert!(matches("[[:xdigit:]][[:xdigit:]]", "fF"));
+        assert!(!matches("[[:unknown:]]", "a"));
+    }
+
+    #[test]
+    fn matches_escaped_characters_literally() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a\\?", "a?"));
+        assert!(!matches("a\\?", "ab"));
+        assert!(matches("[\\]]", "]"));
+        let text = "weird*[name]?";
+        assert!(matches(&escape(text), text));
+        assert!(!matches(&escape(text), "weirdo[name]x"));
+        assert_eq!(unescape(&escape(text)), text);
+        assert!(!has_wildcards(&escape(text)));
+        assert!(has_wildcards("a[b]"));
+        assert!(!has_wildcards("a[b"));
+    }
+
+    //A directory of its own for each test, removed once the test is done
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let path = env::temp_dir().join(format!("my_shell_glob_{}_{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            for file in files {
+                let file = path.join(file);
+                fs::create_dir_all(file.parent().unwrap()).unwrap();
+                if !file.to_string_lossy().ends_with('/') {
+                    fs::write(&file, "").unwrap();
+                }
+            }
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        //Expands pattern within the directory, returning the paths relative to it
+        fn expand(&self, pattern: &str, dotglob: bool, globstar: bool) -> Vec<String> {
+            let dir = format!("{}/", escape(&self.0.to_string_lossy()));
+            let options = PathOptions { dotglob, globstar };
+            expand_path(&format!("{}{}", dir, pattern), &options)
+                .into_iter()
+                .map(|path| path[unescape(&dir).len()..].to_string())
+                .collect()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn expands_paths_in_sorted_order() {
+        let dir = TempDir::new(
+            "sorted",
+            &["b.rs", "a.rs", "c.txt", "src/main.rs", "src/lib.rs"],
+        );
+        assert_eq!(dir.expand("*.rs", false, false), ["a.rs", "b.rs"]);
+        assert_eq!(dir.expand("?.*", false, false), ["a.rs", "b.rs", "c.txt"]);
+        assert_eq!(dir.expand("[bc]*", false, false), ["b.rs", "c.txt"]);
+        assert_eq!(
+            dir.expand("*/*.rs", false, false),
+            ["src/lib.rs", "src/main.rs"]
+        );
+        //A trailing slash only matches directories
+        assert_eq!(dir.expand("*/", false, false), ["src/"]);
+        assert!(dir.expand("*.md", false, false).is_empty());
+    }
+
+    #[test]
+    fn hides_dotfiles_unless_asked_for() {
+        let dir = TempDir::new("dotglob", &[".hidden", "shown", ".config/rc"]);
+        assert_eq!(dir.expand("*", false, false), ["shown"]);
+        assert_eq!(dir.expand(".*", false, false), [".config", ".hidden"]);
+        assert_eq!(
+            dir.expand("*", true, false),
+            [".config", ".hidden", "shown"]
+        );
+        assert_eq!(dir.expand("*/*", true, false), [".config/rc"]);
+        assert!(dir.expand("*/*", false, false).is_empty());
+    }
+
+    #[test]
+    fn expands_globstar_recursively() {
+        let dir = TempDir::new("globstar", &["a.rs", "x/b.rs", "x/y/c.rs", "x/y/d.txt"]);
+        assert_eq!(
+            dir.expand("**/*.rs", false, true),
+            ["a.rs", "x/b.rs", "x/y/c.rs"]
+        );
+        //Like in bash, the directory in front of **/ is one of the matches
+        assert_eq!(dir.expand("**/", false, true), ["", "x/", "x/y/"]);
+        //Without globstar, ** is the same as *
+        assert_eq!(dir.expand("**/*.rs", false, false), ["x/b.rs"]);
+    }
+}
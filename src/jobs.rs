@@ -0,0 +1,416 @@
+use std::{
+    error::Error,
+    fmt, io, mem,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::status::ExitStatus;
+
+/*
+Job control. Every pipeline is a job, whose processes share a process group
+led by the first one. Jobs which are stopped (e.g. by Ctrl-Z) or were started
+in the background with '&' are kept in a table until they have finished.
+*/
+
+/*
+Signals the shell ignores while it manages the terminal: the ones which would stop it,
+and the ones of Ctrl-C and Ctrl-\, which are only meant for the foreground job.
+*/
+const IGNORED_SIGNALS: [i32; 5] = [
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+    libc::SIGINT,
+    libc::SIGQUIT,
+];
+
+//Set by the handler of SIGINT installed while waiting with Job::reap_interruptibly()
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn note_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+#[derive(PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(ExitStatus), //Exit status of its last process
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobState::Running => write!(f, "Running"),
+            JobState::Stopped => write!(f, "Stopped"),
+            JobState::Done(status) => write!(f, "{}", status.describe()),
+        }
+    }
+}
+
+pub struct Job {
+    pub id: usize,  //Number shown in brackets, e.g. [1]. 0 until it's in the table.
+    pub pgid: i32,  //Process group of its processes
+    pids: Vec<i32>, //Its processes, in the order of the pipeline
+    statuses: Vec<Option<ExitStatus>>, //Exit status of each process, once it has been reaped
+    pub command: String, //Source text of the job, as shown by jobs
+    pub state: JobState,
+    pub modes: Option<libc::termios>, //Terminal settings of a stopped job, restored by fg
+    changed: bool,                    //Whether the state has changed since it was last reported
+}
+
+impl Job {
+    //Creates a running job made up of the given processes, which must not be empty
+    pub fn new(pids: Vec<i32>, command: String) -> Self {
+        Job {
+            id: 0,
+            pgid: pids[0],
+            statuses: vec![None; pids.len()],
+            pids,
+            command,
+            state: JobState::Running,
+            modes: None,
+            changed: false,
+        }
+    }
+
+    //Processes which haven't been reaped yet
+    fn remaining(&self) -> impl Iterator<Item = i32> + '_ {
+        let pids = self.pids.iter().zip(&self.statuses);
+        pids.filter(|(_, status)| status.is_none())
+            .map(|(pid, _)| *pid)
+    }
+
+    /*
+    Exit statuses of its processes, in the order of the pipeline. The ones
+    which haven't finished yet count as stopped by Ctrl-Z.
+    */
+    pub fn statuses(&self) -> Vec<ExitStatus> {
+        let stopped = ExitStatus::Stopped(libc::SIGTSTP);
+        let statuses = self.statuses.iter();
+        statuses.map(|status| status.unwrap_or(stopped)).collect()
+    }
+
+    /*
+    Reaps the processes of the job which have finished. With block set, waits until
+    all of them did or the job has been stopped.
+    */
+    pub fn reap(&mut self, block: bool) {
+        let flags = if block {
+            libc::WUNTRACED
+        } else {
+            libc::WUNTRACED | libc::WNOHANG
+        };
+        let previous_state = mem::discriminant(&self.state);
+        'processes: for i in 0..self.pids.len() {
+            while self.statuses[i].is_none() {
+                let mut status = 0;
+                //Safety: status points to a valid integer
+                let result = unsafe { libc::waitpid(self.pids[i], &mut status, flags) };
+                if result == -1 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                if result == 0 {
+                    //Still running
+                    break;
+                }
+                if result > 0 && libc::WIFSTOPPED(status) {
+                    self.state = JobState::Stopped;
+                    if block {
+                        break 'processes;
+                    }
+                    break;
+                }
+                //Either finished or gone, e.g. because it has been reaped already
+                self.statuses[i] = Some(if result > 0 {
+                    ExitStatus::from_wait_status(status)
+                } else {
+                    ExitStatus::NotFound
+                });
+            }
+        }
+        //Processes of a pipeline may finish in any order, the job only once all of them did
+        if self.statuses.iter().all(Option::is_some) {
+            let status = self.statuses.last().copied().flatten();
+            self.state = JobState::Done(status.unwrap_or(ExitStatus::SUCCESS));
+        }
+        self.changed |= mem::discriminant(&self.state) != previous_state;
+    }
+
+    /*
+    Waits like reap(true), but gives up once Ctrl-C is pressed, which the shell
+    otherwise ignores while it controls jobs. Returns false if it was interrupted.
+    */
+    pub fn reap_interruptibly(&mut self) -> bool {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        let handler = note_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        //Safety: the handler only sets an atomic flag, which is async-signal-safe
+        let previous = unsafe { libc::signal(libc::SIGINT, handler) };
+        //Polled, since a blocking waitpid() would be resumed after the signal
+        while self.state == JobState::Running && !INTERRUPTED.load(Ordering::SeqCst) {
+            self.reap(false);
+            if self.state == JobState::Running {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        //Safety: previous is the disposition the signal had before
+        unsafe { libc::signal(libc::SIGINT, previous) };
+        self.state != JobState::Running || !INTERRUPTED.load(Ordering::SeqCst)
+    }
+
+    //Lets the processes of a stopped job continue
+    pub fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        //Safety: kill only sends a signal
+        if unsafe { libc::kill(-self.pgid, libc::SIGCONT) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        self.state = JobState::Running;
+        Ok(())
+    }
+}
+
+pub struct JobTable {
+    jobs: Vec<Job>,     //Ordered by use, the last one being the current job (%+)
+    disowned: Vec<i32>, //Processes of disowned jobs, reaped silently
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        JobTable {
+            jobs: Vec::new(),
+            disowned: Vec::new(),
+        }
+    }
+
+    /*
+    Adds a job, which becomes the current one, and returns its number. A job which
+    has been in the table before (e.g. one stopped again after fg) keeps its number,
+    otherwise the lowest one not used by another job is taken.
+    */
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = (1..)
+                .find(|id| self.jobs.iter().all(|job| job.id != *id))
+                .unwrap_or_default();
+        }
+        job.changed = false;
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    //Makes a job the current one
+    pub fn touch(&mut self, id: usize) {
+        if let Some(job) = self.remove(id) {
+            self.jobs.push(job);
+        }
+    }
+
+    //Removes a job from the table without terminating its processes
+    pub fn disown(&mut self, id: usize) {
+        if let Some(job) = self.remove(id) {
+            self.disowned.extend(job.remaining());
+        }
+    }
+
+    //Numbers of the jobs which are still running, in ascending order
+    pub fn running(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self
+            .jobs
+            .iter()
+            .filter(|job| job.state == JobState::Running)
+            .map(|job| job.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /*
+    Finds the number of the job matching spec, which is either a number (optionally
+    preceded by %), %+ or %% for the current job, %- for the previous one or %name
+    for the job whose command starts with name. Without spec, it's the current job.
+    */
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, Box<dyn Error>> {
+        let spec = spec.unwrap_or("%+");
+        let name = spec.strip_prefix('%').unwrap_or(spec);
+        let job = match name {
+            "" | "+" | "%" => self.jobs.last(),
+            "-" => self.jobs.iter().rev().nth(1),
+            _ => match name.parse::<usize>() {
+                Ok(id) => self.jobs.iter().find(|job| job.id == id),
+                Err(_) => self.jobs.iter().find(|job| job.command.starts_with(name)),
+            },
+        };
+        job.map(|job| job.id)
+            .ok_or_else(|| format!("{}: no such job", spec).into())
+    }
+
+    //Finds the number of the job a process belongs to
+    pub fn find_pid(&self, pid: i32) -> Option<usize> {
+        let job = self
+            .jobs
+            .iter()
+            .find(|job| job.remaining().any(|p| p == pid))?;
+        Some(job.id)
+    }
+
+    //Reaps the processes of all jobs which have finished, without blocking
+    pub fn update(&mut self) {
+        for job in &mut self.jobs {
+            if !matches!(job.state, JobState::Done(_)) {
+                job.reap(false);
+            }
+        }
+        self.disowned.retain(|pid| {
+            let mut status = 0;
+            //Safety: status points to a valid integer
+            unsafe { libc::waitpid(*pid, &mut status, libc::WNOHANG) == 0 }
+        });
+    }
+
+    /*
+    Returns the status lines of the jobs whose state has changed since they were last
+    reported. Jobs which have finished are removed from the table afterwards.
+    */
+    pub fn take_changes(&mut self) -> Vec<String> {
+        let changes = self
+            .jobs
+            .iter()
+            .filter(|job| job.changed)
+            .map(|job| self.format_status(job))
+            .collect();
+        for job in &mut self.jobs {
+            job.changed = false;
+        }
+        self.jobs
+            .retain(|job| !matches!(job.state, JobState::Done(_)));
+        changes
+    }
+
+    //Status lines of all jobs, in the order of their numbers
+    pub fn status_lines(&self) -> Vec<String> {
+        self.sorted().map(|job| self.format_status(job)).collect()
+    }
+
+    //Process groups of all jobs, in the order of their numbers
+    pub fn process_groups(&self) -> Vec<i32> {
+        self.sorted().map(|job| job.pgid).collect()
+    }
+
+    pub fn status_line(&self, id: usize) -> Option<String> {
+        let job = self.jobs.iter().find(|job| job.id == id)?;
+        Some(self.format_status(job))
+    }
+
+    fn sorted(&self) -> impl Iterator<Item = &Job> {
+        let mut jobs: Vec<&Job> = self.jobs.iter().collect();
+        jobs.sort_by_key(|job| job.id);
+        jobs.into_iter()
+    }
+
+    //E.g. "[1]+  Stopped                 vim notes", '+' marking the current job and '-' the previous one
+    fn format_status(&self, job: &Job) -> String {
+        let marker = match self.jobs.iter().rev().position(|other| other.id == job.id) {
+            Some(0) => '+',
+            Some(1) => '-',
+            _ => ' ',
+        };
+        let suffix = if job.state == JobState::Running {
+            " &"
+        } else {
+            ""
+        };
+        let state = job.state.to_string();
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            job.id, marker, state, job.command, suffix
+        )
+    }
+}
+
+/*
+Takes control of the terminal (stdin) for the shell: it moves into a process group
+of its own, which becomes the foreground one, and ignores the signals which would
+otherwise stop it or are meant for jobs. Returns the terminal's settings, which are restored after each job.
+*/
+pub fn take_terminal() -> Option<libc::termios> {
+    //Safety: the calls only change signal dispositions, the process group and the terminal's foreground group
+    unsafe {
+        for signal in IGNORED_SIGNALS {
+            libc::signal(signal, libc::SIG_IGN);
+        }
+        libc::setpgid(0, 0);
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+    }
+    terminal_modes()
+}
+
+/*
+Prepares a child process of the shell for running as part of a job: moves it into
+the job's process group (0 for a new one led by itself), hands it the terminal if the
+job runs in the foreground and restores the signals the shell ignores. Only uses
+async-signal-safe functions, so that it can be called between fork() and exec().
+*/
+pub fn enter_job(pgid: i32, foreground: bool) {
+    //Safety: the calls only change the process group and the terminal's foreground group
+    unsafe {
+        libc::setpgid(0, pgid);
+        if foreground {
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+        }
+    }
+    restore_signals();
+}
+
+/*
+Lets a child process of the shell be stopped and interrupted again, like by Ctrl-C,
+which the shell itself ignores. Async-signal-safe like enter_job().
+*/
+pub fn restore_signals() {
+    for signal in IGNORED_SIGNALS {
+        //Safety: signal only changes the disposition of the signal
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+//Makes the process group the foreground one of the terminal
+pub fn set_foreground(pgid: i32) {
+    //Safety: tcsetpgrp only changes the terminal's foreground group
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid) };
+}
+
+//Gives the terminal back to the shell after a job, restoring the shell's settings
+pub fn reclaim_terminal(modes: Option<&libc::termios>) {
+    //Safety: getpgrp has no preconditions
+    set_foreground(unsafe { libc::getpgrp() });
+    if let Some(modes) = modes {
+        set_terminal_modes(modes);
+    }
+}
+
+pub fn terminal_modes() -> Option<libc::termios> {
+    //Safety: termios is plain data, which tcgetattr fills in
+    unsafe {
+        let mut modes: libc::termios = mem::zeroed();
+        match libc::tcgetattr(libc::STDIN_FILENO, &mut modes) {
+            0 => Some(modes),
+            _ => None,
+        }
+    }
+}
+
+pub fn set_terminal_modes(modes: &libc::termios) {
+    //Safety: modes points to valid settings
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, modes) };
+}
@@ -0,0 +1,1022 @@
+use std::{error::Error, fmt};
+
+use crate::ast::{Parameter, ParameterOperation, Word, WordPart};
+
+//Control and redirection operators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Pipe,      //|
+    AndIf,     //&&
+    OrIf,      //||
+    Semi,      //;
+    DSemi,     //;;, ends an item of case
+    Amp,       //&
+    Great,     //>
+    DGreat,    //>>
+    Less,      //<
+    TLess,     //<<<
+    GreatAnd,  //>&
+    LessAnd,   //<&
+    AndGreat,  //&>
+    AndDGreat, //&>>
+    DLess,     //<<
+    DLessDash, //<<-
+    LParen,    //(
+    RParen,    //)
+}
+
+impl Operator {
+    //Every operator with its spelling. Longer ones come first, so that e.g. ">>" isn't read as two '>'.
+    const ALL: [(&'static str, Operator); 18] = [
+        ("<<<", Operator::TLess),
+        ("<<-", Operator::DLessDash),
+        ("&>>", Operator::AndDGreat),
+        ("&&", Operator::AndIf),
+        ("||", Operator::OrIf),
+        (">>", Operator::DGreat),
+        (">&", Operator::GreatAnd),
+        ("<&", Operator::LessAnd),
+        ("&>", Operator::AndGreat),
+        ("<<", Operator::DLess),
+        (";;", Operator::DSemi),
+        ("|", Operator::Pipe),
+        (";", Operator::Semi),
+        ("&", Operator::Amp),
+        (">", Operator::Great),
+        ("<", Operator::Less),
+        ("(", Operator::LParen),
+        (")", Operator::RParen),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(_, op)| op == self)
+            .map(|(spelling, _)| *spelling)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    Op(Operator),
+    IoNumber(u32),    //Digits right in front of a redirection operator, as in 2>file
+    Arithmetic(Word), //Expression of an arithmetic command ((...))
+    Newline,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(_) => write!(f, "word"),
+            Token::Op(op) => write!(f, "'{}'", op.as_str()),
+            Token::IoNumber(fd) => write!(f, "'{}'", fd),
+            Token::Arithmetic(_) => write!(f, "'(('"),
+            Token::Newline => write!(f, "newline"),
+        }
+    }
+}
+
+//Returned if the input ends before a construct is closed, so that more lines can be read
+#[derive(Debug)]
+pub struct IncompleteInput(pub String);
+
+impl fmt::Display for IncompleteInput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unexpected end of input: {}", self.0)
+    }
+}
+
+impl Error for IncompleteInput {}
+
+/*
+Splits the user's input into tokens. Words keep track of their quoting
+and of the command substitutions they contain, so that the parser and
+the expansion stage don't need to look at raw characters again.
+
+The delimiter word of a here-document (<<EOF) is replaced by the
+here-document's body, which is read from the lines following it.
+*/
+pub fn tokenize(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let mut lexer = Lexer::new(input);
+    while let Some(token) = lexer.next_token()? {
+        if let (Some(Token::Op(op)), Token::Word(delimiter)) = (lexer.tokens.last(), &token) {
+            if let Operator::DLess | Operator::DLessDash = op {
+                lexer.pending_heredocs.push(PendingHereDoc {
+                    token_index: lexer.tokens.len(),
+                    delimiter: unquoted_text(delimiter),
+                    quoted: delimiter.iter().any(|p| !matches!(p, WordPart::Literal(_))),
+                    strip_tabs: *op == Operator::DLessDash,
+                });
+            }
+        }
+        lexer.tokens.push(token);
+    }
+    if let Some(heredoc) = lexer.pending_heredocs.first() {
+        let msg = format!("here-document delimited by '{}'", heredoc.delimiter);
+        return Err(Box::new(IncompleteInput(msg)));
+    }
+    Ok(lexer.tokens)
+}
+
+//A here-document whose body hasn't been read yet, since it starts on the next line
+struct PendingHereDoc {
+    token_index: usize, //Index of the delimiter's token, which is replaced by the body
+    delimiter: String,
+    quoted: bool,     //Quoted delimiters (<<'EOF') disable expansion within the body
+    strip_tabs: bool, //<<- removes leading tabs from each line
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize, //Index of the next character to be read
+    tokens: Vec<Token>,
+    pending_heredocs: Vec<PendingHereDoc>,
+}
+
+impl Lexer {
+    fn new(input: &str) -> Self {
+        Self {
+            chars: input.chars().collect(),
+            pos: 0,
+            tokens: Vec::new(),
+            pending_heredocs: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, Box<dyn Error>> {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                self.pos += 1;
+                self.read_heredoc_bodies()?;
+                return Ok(Some(Token::Newline));
+            } else if c.is_whitespace() {
+                self.pos += 1;
+            } else if c == '\\' && self.peek_at(1) == Some('\n') {
+                //An escaped newline continues the line, which may still have to be read
+                if self.peek_at(2).is_none() {
+                    return Err(Box::new(IncompleteInput("line continuation".to_string())));
+                }
+                self.pos += 2;
+            } else if c == '#' {
+                //A comment runs to the end of the line, only a word starting with '#' begins one
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+        //((...)) is an arithmetic command, unless it turns out to be nested subshells, e.g. ((cd a); ls)
+        if self.peek() == Some('(') && self.peek_at(1) == Some('(') {
+            let start = self.pos;
+            self.pos += 1;
+            if let Some(expression) = self.read_arithmetic()? {
+                return Ok(Some(Token::Arithmetic(expression)));
+            }
+            self.pos = start;
+        }
+        if let Some(op) = self.operator_at_pos() {
+            self.pos += op.as_str().chars().count();
+            return Ok(Some(Token::Op(op)));
+        }
+        if let Some(fd) = self.io_number_at_pos() {
+            return Ok(Some(Token::IoNumber(fd)));
+        }
+        Ok(Some(Token::Word(self.read_word()?)))
+    }
+
+    //Returns the operator starting at the current position, if there is one
+    fn operator_at_pos(&self) -> Option<Operator> {
+        Operator::ALL
+            .iter()
+            .find(|(spelling, _)| {
+                spelling
+                    .chars()
+                    .enumerate()
+                    .all(|(i, c)| self.peek_at(i) == Some(c))
+            })
+            .map(|(_, op)| *op)
+    }
+
+    //Consumes and returns the digits at the current position, if they are followed by '<' or '>'
+    fn io_number_at_pos(&mut self) -> Option<u32> {
+        let digits = self.chars[self.pos..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits == 0 || !matches!(self.peek_at(digits), Some('<') | Some('>')) {
+            return None;
+        }
+        let fd = self.chars[self.pos..self.pos + digits]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()?;
+        self.pos += digits;
+        Some(fd)
+    }
+
+    //Whether the current character ends an unquoted word
+    fn at_delimiter(&self) -> bool {
+        match self.peek() {
+            Some(c) => c.is_whitespace() || self.operator_at_pos().is_some(),
+            None => true,
+        }
+    }
+
+    fn read_word(&mut self) -> Result<Word, Box<dyn Error>> {
+        self.read_word_until(|lexer| lexer.at_delimiter())
+    }
+
+    //Reads a word which, unlike a regular one, may contain unquoted whitespace. It ends at stop or the end of input.
+    fn read_operand(&mut self, stop: Option<char>) -> Result<Word, Box<dyn Error>> {
+        self.read_word_until(|lexer| stop.is_some() && lexer.peek() == stop)
+    }
+
+    //Reads the parts of a word until at_end returns true for an unquoted character
+    fn read_word_until(&mut self, at_end: impl Fn(&Self) -> bool) -> Result<Word, Box<dyn Error>> {
+        let mut word = Word::new();
+        let mut literal = String::new();
+        while let Some(c) = self.peek() {
+            if at_end(self) {
+                break;
+            }
+            match c {
+                '\'' => {
+                    push_literal(&mut word, &mut literal);
+                    self.pos += 1;
+                    word.push(WordPart::SingleQuoted(self.read_until_quote('\'')?));
+                }
+                '"' => {
+                    push_literal(&mut word, &mut literal);
+                    self.pos += 1;
+                    word.push(WordPart::DoubleQuoted(self.read_expandable(Some('"'))?));
+                }
+                '$' if self.peek_at(1) == Some('\'') => {
+                    push_literal(&mut word, &mut literal);
+                    self.pos += 2;
+                    word.push(WordPart::SingleQuoted(self.read_ansi_c_quoted()?));
+                }
+                '$' if self.dollar_expansion_at_pos() => {
+                    push_literal(&mut word, &mut literal);
+                    word.push(self.read_dollar_expansion()?);
+                }
+                '`' => {
+                    push_literal(&mut word, &mut literal);
+                    self.pos += 1;
+                    word.push(WordPart::Substitution(self.read_backquoted()?));
+                }
+                //A backslash quotes the next character, or removes it if it's a newline
+                '\\' => match self.peek_at(1) {
+                    //The line continues on the next one, unless that hasn't been read yet
+                    Some('\n') if self.peek_at(2).is_some() => self.pos += 2,
+                    None | Some('\n') => {
+                        return Err(Box::new(IncompleteInput("line continuation".to_string())));
+                    }
+                    Some(next) => {
+                        push_literal(&mut word, &mut literal);
+                        self.pos += 2;
+                        word.push(WordPart::SingleQuoted(next.to_string()));
+                    }
+                },
+                _ => {
+                    literal.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        push_literal(&mut word, &mut literal);
+        Ok(word)
+    }
+
+    /*
+    Reads everything up to the closing quote, which is consumed but not returned.
+    Within double quotes, a backslash keeps the next character from closing them,
+    but is left in the returned text.
+    */
+    fn read_until_quote(&mut self, quote: char) -> Result<String, Box<dyn Error>> {
+        let mut content = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == quote {
+                return Ok(content);
+            }
+            content.push(c);
+            if c == '\\' && quote == '"' {
+                if let Some(next) = self.peek() {
+                    content.push(next);
+                    self.pos += 1;
+                }
+            }
+        }
+        Err(Box::new(IncompleteInput(format!(
+            "missing closing {}",
+            quote
+        ))))
+    }
+
+    /*
+    Reads text in which substitutions are expanded, but which isn't split into words:
+    the contents of "..." (terminator is Some('"'), the opening quote has already
+    been consumed) or the body of a here-document (terminator is None).
+    A backslash only escapes '$', '`', '\\', a newline and the terminator, otherwise it's kept.
+    */
+    fn read_expandable(
+        &mut self,
+        terminator: Option<char>,
+    ) -> Result<Vec<WordPart>, Box<dyn Error>> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        while let Some(c) = self.peek() {
+            if Some(c) == terminator {
+                self.pos += 1;
+                push_literal(&mut parts, &mut literal);
+                return Ok(parts);
+            } else if c == '$' && self.dollar_expansion_at_pos() {
+                push_literal(&mut parts, &mut literal);
+                parts.push(self.read_dollar_expansion()?);
+            } else if c == '`' {
+                push_literal(&mut parts, &mut literal);
+                self.pos += 1;
+                parts.push(WordPart::Substitution(self.read_backquoted()?));
+            } else if c == '\\' && self.peek_at(1) == Some('\n') {
+                self.pos += 2;
+            } else if c == '\\'
+                && self.peek_at(1).is_some_and(|next| {
+                    matches!(next, '$' | '`' | '\\') || Some(next) == terminator
+                })
+            {
+                //Unwrap() will not panic, since the next character has just been checked
+                literal.push(self.peek_at(1).unwrap());
+                self.pos += 2;
+            } else {
+                literal.push(c);
+                self.pos += 1;
+            }
+        }
+        if terminator.is_some() {
+            return Err(Box::new(IncompleteInput("missing closing \"".to_string())));
+        }
+        push_literal(&mut parts, &mut literal);
+        Ok(parts)
+    }
+
+    /*
+    Reads the bodies of the here-documents started on the line that just ended,
+    replacing each delimiter token with the corresponding body.
+    */
+    fn read_heredoc_bodies(&mut self) -> Result<(), Box<dyn Error>> {
+        for heredoc in std::mem::take(&mut self.pending_heredocs) {
+            let mut body = String::new();
+            loop {
+                if self.peek().is_none() {
+                    let msg = format!("here-document delimited by '{}'", heredoc.delimiter);
+                    return Err(Box::new(IncompleteInput(msg)));
+                }
+                let mut line = String::new();
+                while let Some(c) = self.peek() {
+                    self.pos += 1;
+                    if c == '\n' {
+                        break;
+                    }
+                    line.push(c);
+                }
+                if heredoc.strip_tabs {
+                    line = line.trim_start_matches('\t').to_string();
+                }
+                if line == heredoc.delimiter {
+                    break;
+                }
+                body.push_str(&line);
+                body.push('\n');
+            }
+
+            let body = if heredoc.quoted {
+                vec![WordPart::SingleQuoted(body)]
+            } else {
+                let mut body_lexer = Lexer::new(&body);
+                vec![WordPart::DoubleQuoted(body_lexer.read_expandable(None)?)]
+            };
+            self.tokens[heredoc.token_index] = Token::Word(body);
+        }
+        Ok(())
+    }
+
+    //Whether the '$' at the current position starts an expansion rather than being a plain character
+    fn dollar_expansion_at_pos(&self) -> bool {
+        match self.peek_at(1) {
+            Some(c) => {
+                c == '{'
+                    || c == '('
+                    || c == '_'
+                    || c.is_ascii_alphanumeric()
+                    || is_special_parameter(c)
+            }
+            None => false,
+        }
+    }
+
+    /*
+    Reads an expansion starting with '$': a parameter ($NAME, $1, $?, ${NAME})
+    or a command substitution ($(whoami), or the legacy ${whoami} if the braces
+    don't hold a name).
+    */
+    fn read_dollar_expansion(&mut self) -> Result<WordPart, Box<dyn Error>> {
+        self.pos += 1;
+        //Unwrap() will not panic, since dollar_expansion_at_pos() made sure there is a next character
+        let c = self.peek().unwrap();
+        self.pos += 1;
+        if c == '(' {
+            //$((...)) is arithmetic, unless it turns out to be a subcommand starting with a subshell
+            if self.peek() == Some('(') {
+                let start = self.pos;
+                if let Some(expression) = self.read_arithmetic()? {
+                    return Ok(WordPart::Arithmetic(expression));
+                }
+                self.pos = start;
+            }
+            return Ok(WordPart::Substitution(self.read_until_matching('(', ')')?));
+        }
+        if c == '{' {
+            let source = self.read_until_matching('{', '}')?;
+            return Ok(match parse_braced_parameter(&source)? {
+                Some(parameter) => WordPart::Parameter(parameter),
+                None => WordPart::LegacySubstitution(source),
+            });
+        }
+
+        let mut name = c.to_string();
+        //Positional ($1) and special parameters ($?) are a single character long
+        if c == '_' || c.is_ascii_alphabetic() {
+            while let Some(c) = self.peek() {
+                if c != '_' && !c.is_ascii_alphanumeric() {
+                    break;
+                }
+                name.push(c);
+                self.pos += 1;
+            }
+        }
+        Ok(WordPart::Parameter(Parameter {
+            name,
+            braced: false,
+            index: None,
+            operation: None,
+        }))
+    }
+
+    /*
+    Reads an arithmetic expression enclosed in double parentheses, starting at
+    the inner '('. Returns None, if the parentheses don't close right after each other.
+    */
+    fn read_arithmetic(&mut self) -> Result<Option<Word>, Box<dyn Error>> {
+        self.pos += 1;
+        let source = self.read_until_matching('(', ')')?;
+        if self.peek() != Some(')') {
+            return Ok(None);
+        }
+        self.pos += 1;
+        //Parameters and subcommands within the expression are expanded first
+        Ok(Some(Lexer::new(&source).read_expandable(None)?))
+    }
+
+    //Reads the name of a parameter within ${...}: a variable name, a number or a special character
+    fn read_parameter_name(&mut self) -> Option<String> {
+        let first = self.peek()?;
+        let length = if is_special_parameter(first) {
+            1
+        } else if first.is_ascii_digit() {
+            self.chars[self.pos..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .count()
+        } else if first == '_' || first.is_ascii_alphabetic() {
+            self.chars[self.pos..]
+                .iter()
+                .take_while(|c| **c == '_' || c.is_ascii_alphanumeric())
+                .count()
+        } else {
+            return None;
+        };
+        let name = self.chars[self.pos..self.pos + length].iter().collect();
+        self.pos += length;
+        Some(name)
+    }
+
+    //Reads the operation following the name within ${...}, or returns None if there is no valid one
+    fn read_parameter_operation(&mut self) -> Result<Option<ParameterOperation>, Box<dyn Error>> {
+        let check_null = self.peek() == Some(':')
+            && matches!(
+                self.peek_at(1),
+                Some('-') | Some('=') | Some('?') | Some('+')
+            );
+        if check_null {
+            self.pos += 1;
+        }
+        let Some(op) = self.peek() else {
+            return Ok(None);
+        };
+        self.pos += 1;
+        //The operator may be doubled, e.g. ## or //
+        let doubled = matches!(op, '#' | '%' | '/') && self.peek() == Some(op);
+        if doubled {
+            self.pos += 1;
+        }
+
+        let operation = match op {
+            '-' => ParameterOperation::UseDefault {
+                word: self.read_operand(None)?,
+                check_null,
+            },
+            '=' => ParameterOperation::AssignDefault {
+                word: self.read_operand(None)?,
+                check_null,
+            },
+            '?' => ParameterOperation::ErrorIfUnset {
+                word: self.read_operand(None)?,
+                check_null,
+            },
+            '+' => ParameterOperation::UseAlternative {
+                word: self.read_operand(None)?,
+                check_null,
+            },
+            '#' => ParameterOperation::RemovePrefix {
+                pattern: self.read_operand(None)?,
+                longest: doubled,
+            },
+            '%' => ParameterOperation::RemoveSuffix {
+                pattern: self.read_operand(None)?,
+                longest: doubled,
+            },
+            '/' => {
+                let pattern = self.read_operand(Some('/'))?;
+                if self.peek() == Some('/') {
+                    self.pos += 1;
+                }
+                ParameterOperation::Replace {
+                    pattern,
+                    replacement: self.read_operand(None)?,
+                    all: doubled,
+                }
+            }
+            ':' => {
+                let offset = self.read_operand(Some(':'))?;
+                let mut length = None;
+                if self.peek() == Some(':') {
+                    self.pos += 1;
+                    length = Some(self.read_operand(None)?);
+                }
+                ParameterOperation::Substring { offset, length }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(operation))
+    }
+
+    /*
+    Reads the source of a substitution up to the close matching the already consumed
+    open, e.g. the ')' of "$(". Nested substitutions are kept as they are and get
+    parsed once the outer one is executed.
+    */
+    fn read_until_matching(&mut self, open: char, close: char) -> Result<String, Box<dyn Error>> {
+        let mut source = String::new();
+        let mut depth = 1;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '\\' {
+                //Escaped characters neither open nor close anything, but are kept for the nested input
+                source.push(c);
+                if let Some(next) = self.peek() {
+                    source.push(next);
+                    self.pos += 1;
+                }
+                continue;
+            } else if c == '\'' || c == '"' {
+                source.push(c);
+                source.push_str(&self.read_until_quote(c)?);
+            } else if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(source);
+                }
+            }
+            source.push(c);
+        }
+        let msg = format!("missing closing {} of substitution", close);
+        Err(Box::new(IncompleteInput(msg)))
+    }
+
+    /*
+    Reads the contents of an ANSI-C quoted string $'...' (the opening $' has already been consumed),
+    replacing escape sequences like \\n, \\t, \\x41 or \\u00e9 by the characters they stand for.
+    */
+    fn read_ansi_c_quoted(&mut self) -> Result<String, Box<dyn Error>> {
+        //Escapes like \\xc3\\xa9 produce single bytes, which only form characters together
+        let mut bytes = Vec::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '\'' {
+                return Ok(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            if c != '\\' {
+                bytes.extend(c.to_string().as_bytes());
+                continue;
+            }
+            let Some(escaped) = self.peek() else {
+                bytes.push(b'\\');
+                continue;
+            };
+            self.pos += 1;
+            let simple = match escaped {
+                'a' => Some('\x07'),
+                'b' => Some('\x08'),
+                'e' | 'E' => Some('\x1b'),
+                'f' => Some('\x0c'),
+                'n' => Some('\n'),
+                'r' => Some('\r'),
+                't' => Some('\t'),
+                'v' => Some('\x0b'),
+                '\\' | '\'' | '"' | '?' => Some(escaped),
+                _ => None,
+            };
+            if let Some(c) = simple {
+                bytes.extend(c.to_string().as_bytes());
+                continue;
+            }
+            match escaped {
+                '0'..='7' => {
+                    self.pos -= 1;
+                    let value = self.read_digits(8, 3);
+                    bytes.push(value as u8);
+                }
+                'x' | 'u' | 'U' => {
+                    let max_digits = match escaped {
+                        'x' => 2,
+                        'u' => 4,
+                        _ => 8,
+                    };
+                    let start = self.pos;
+                    let value = self.read_digits(16, max_digits);
+                    if self.pos == start {
+                        //Without any digits, the sequence is taken literally
+                        bytes.push(b'\\');
+                        bytes.extend(escaped.to_string().as_bytes());
+                    } else if escaped == 'x' {
+                        bytes.push(value as u8);
+                    } else {
+                        let c = char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER);
+                        bytes.extend(c.to_string().as_bytes());
+                    }
+                }
+                //Control characters, e.g. \\cA
+                'c' => {
+                    if let Some(c) = self.peek() {
+                        self.pos += 1;
+                        bytes.push(c as u8 & 0x1f);
+                    }
+                }
+                _ => {
+                    bytes.push(b'\\');
+                    bytes.extend(escaped.to_string().as_bytes());
+                }
+            }
+        }
+        Err(Box::new(IncompleteInput("missing closing '".to_string())))
+    }
+
+    //Reads up to max_digits digits of the given radix as a number
+    fn read_digits(&mut self, radix: u32, max_digits: usize) -> u32 {
+        let mut value = 0;
+        for _ in 0..max_digits {
+            match self.peek().and_then(|c| c.to_digit(radix)) {
+                Some(digit) => {
+                    value = value * radix + digit;
+                    self.pos += 1;
+                }
+                None => break,
+            }
+        }
+        value
+    }
+
+    /*
+    Reads the source of a `...` command substitution (the opening backquote has already
+    been consumed). Within it, a backslash escapes '`', '$' and another backslash.
+    */
+    fn read_backquoted(&mut self) -> Result<String, Box<dyn Error>> {
+        let mut source = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '`' => return Ok(source),
+                '\\' => match self.peek() {
+                    Some(next @ ('`' | '$' | '\\')) => {
+                        source.push(next);
+                        self.pos += 1;
+                    }
+                    _ => source.push(c),
+                },
+                _ => source.push(c),
+            }
+        }
+        Err(Box::new(IncompleteInput("missing closing `".to_string())))
+    }
+}
+
+//Returns the text of a word with its quotes removed, as needed for here-document delimiters
+fn unquoted_text(word: &Word) -> String {
+    word.iter()
+        .map(|part| match part {
+            WordPart::Literal(text) | WordPart::SingleQuoted(text) => text.clone(),
+            WordPart::DoubleQuoted(parts) => unquoted_text(parts),
+            WordPart::Substitution(source) => format!("$({})", source),
+            WordPart::LegacySubstitution(source) => format!("${{{}}}", source),
+            WordPart::Parameter(parameter) if parameter.braced => {
+                format!("${{{}}}", parameter.name)
+            }
+            WordPart::Parameter(parameter) => format!("${}", parameter.name),
+            WordPart::Arithmetic(parts) => format!("$(({}))", unquoted_text(parts)),
+        })
+        .collect()
+}
+
+/*
+Parses the contents of ${...} into a parameter, along with the operation applied to it.
+Returns None, if they don't form a valid parameter expansion (e.g. ${ls -la}).
+*/
+fn parse_braced_parameter(source: &str) -> Result<Option<Parameter>, Box<dyn Error>> {
+    let mut lexer = Lexer::new(source);
+    //${#NAME} is the length of NAME, whereas ${#} is the number of positional parameters
+    let length = source.len() > 1 && source.starts_with('#');
+    if length {
+        lexer.pos += 1;
+    }
+    let Some(name) = lexer.read_parameter_name() else {
+        return Ok(None);
+    };
+    //Subscript of an array element, expanded like "..." before it's evaluated
+    let mut index = None;
+    if lexer.peek() == Some('[') && is_variable_name(&name) {
+        lexer.pos += 1;
+        let Ok(source) = lexer.read_until_matching('[', ']') else {
+            return Ok(None);
+        };
+        index = Some(Lexer::new(&source).read_expandable(None)?);
+    }
+
+    let operation = if length {
+        if lexer.peek().is_some() {
+            return Ok(None);
+        }
+        Some(ParameterOperation::Length)
+    } else if lexer.peek().is_none() {
+        None
+    } else {
+        match lexer.read_parameter_operation()? {
+            Some(operation) => Some(operation),
+            None => return Ok(None),
+        }
+    };
+    Ok(Some(Parameter {
+        name,
+        braced: true,
+        index,
+        operation,
+    }))
+}
+
+//Parameters whose name is a single special character, e.g. $? for the exit code
+pub fn is_special_parameter(c: char) -> bool {
+    matches!(c, '?' | '$' | '!' | '#' | '@' | '*')
+}
+
+//Whether name is a valid name for a variable to be assigned to (letters, digits and '_', not starting with a digit)
+pub fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+//Moves pending unquoted text into the word as a literal part
+pub fn push_literal(parts: &mut Vec<WordPart>, literal: &mut String) {
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(std::mem::take(literal)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Parameter;
+
+    fn literal(text: &str) -> Token {
+        Token::Word(vec![WordPart::Literal(text.to_string())])
+    }
+
+    fn parameter(name: &str, braced: bool) -> WordPart {
+        WordPart::Parameter(Parameter {
+            name: name.to_string(),
+            braced,
+            index: None,
+            operation: None,
+        })
+    }
+
+    fn is_incomplete(input: &str) -> bool {
+        matches!(tokenize(input), Err(e) if e.is::<IncompleteInput>())
+    }
+
+    #[test]
+    fn splits_words_and_operators() {
+        let tokens = tokenize("ls -l|wc&&echo ok;;\n").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                literal("ls"),
+                literal("-l"),
+                Token::Op(Operator::Pipe),
+                literal("wc"),
+                Token::Op(Operator::AndIf),
+                literal("echo"),
+                literal("ok"),
+                Token::Op(Operator::DSemi),
+                Token::Newline,
+            ]
+        );
+        //The longest operator wins
+        let tokens = tokenize("a >>b &>>c <<<d").unwrap();
+        assert_eq!(tokens[1], Token::Op(Operator::DGreat));
+        assert_eq!(tokens[3], Token::Op(Operator::AndDGreat));
+        assert_eq!(tokens[5], Token::Op(Operator::TLess));
+    }
+
+    #[test]
+    fn keeps_quoting() {
+        let tokens = tokenize("echo 'a $b' \"c $d\" e\\ f").unwrap();
+        assert_eq!(
+            tokens[1..],
+            [
+                Token::Word(vec![WordPart::SingleQuoted("a $b".to_string())]),
+                Token::Word(vec![WordPart::DoubleQuoted(vec![
+                    WordPart::Literal("c ".to_string()),
+                    parameter("d", false),
+                ])]),
+                Token::Word(vec![
+                    WordPart::Literal("e".to_string()),
+                    WordPart::SingleQuoted(" ".to_string()),
+                    WordPart::Literal("f".to_string()),
+                ]),
+            ]
+        );
+        //An empty pair of quotes is still a word
+        assert_eq!(tokenize("''").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reads_parameters_and_substitutions() {
+        let tokens = tokenize("echo $HOME${00}$(whoami)`date`").unwrap();
+        assert_eq!(
+            tokens[1],
+            Token::Word(vec![
+                parameter("HOME", false),
+                parameter("00", true),
+                WordPart::Substitution("whoami".to_string()),
+                WordPart::Substitution("date".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn reads_io_numbers_only_right_before_redirections() {
+        let tokens = tokenize("cmd 2>err 2 >out a2>f 3<&0").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                literal("cmd"),
+                Token::IoNumber(2),
+                Token::Op(Operator::Great),
+                literal("err"),
+                literal("2"),
+                Token::Op(Operator::Great),
+                literal("out"),
+                literal("a2"),
+                Token::Op(Operator::Great),
+                literal("f"),
+                Token::IoNumber(3),
+                Token::Op(Operator::LessAnd),
+                literal("0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tells_arithmetic_from_nested_parentheses() {
+        assert_eq!(
+            tokenize("((x += 1))").unwrap(),
+            [Token::Arithmetic(vec![WordPart::Literal(
+                "x += 1".to_string()
+            )])]
+        );
+        assert_eq!(
+            tokenize("echo $((1 + 2))").unwrap()[1],
+            Token::Word(vec![WordPart::Arithmetic(vec![WordPart::Literal(
+                "1 + 2".to_string()
+            )])])
+        );
+        //Parentheses closed separately are subshells, not arithmetic
+        assert_eq!(
+            tokenize("((echo a); echo b)").unwrap()[..3],
+            [
+                Token::Op(Operator::LParen),
+                Token::Op(Operator::LParen),
+                literal("echo"),
+            ]
+        );
+    }
+
+    #[test]
+    fn replaces_heredoc_delimiters_by_their_bodies() {
+        let tokens = tokenize("cat <<EOF; echo done\nhi $x\nEOF\n").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                literal("cat"),
+                Token::Op(Operator::DLess),
+                Token::Word(vec![WordPart::DoubleQuoted(vec![
+                    WordPart::Literal("hi ".to_string()),
+                    parameter("x", false),
+                    WordPart::Literal("\n".to_string()),
+                ])]),
+                Token::Op(Operator::Semi),
+                literal("echo"),
+                literal("done"),
+                Token::Newline,
+            ]
+        );
+        //A quoted delimiter disables expansion, <<- strips leading tabs
+        let tokens = tokenize("cat <<'E'\n$x\nE\n").unwrap();
+        assert_eq!(
+            tokens[2],
+            Token::Word(vec![WordPart::SingleQuoted("$x\n".to_string())])
+        );
+        let tokens = tokenize("cat <<-E\n\tab\n\tE\n").unwrap();
+        assert_eq!(
+            tokens[2],
+            Token::Word(vec![WordPart::DoubleQuoted(vec![WordPart::Literal(
+                "ab\n".to_string()
+            )])])
+        );
+    }
+
+    #[test]
+    fn reserved_words_are_plain_words() {
+        //Only the parser knows where a word is reserved
+        assert_eq!(
+            tokenize("if then").unwrap(),
+            [literal("if"), literal("then")]
+        );
+    }
+
+    #[test]
+    fn continues_escaped_newlines() {
+        assert_eq!(
+            tokenize("echo a \\\n b").unwrap(),
+            [literal("echo"), literal("a"), literal("b")]
+        );
+        assert_eq!(
+            tokenize("echo c\\\nd").unwrap(),
+            [literal("echo"), literal("cd")]
+        );
+    }
+
+    #[test]
+    fn reports_incomplete_input() {
+        assert!(is_incomplete("echo 'abc"));
+        assert!(is_incomplete("echo \"abc"));
+        assert!(is_incomplete("echo $(ls"));
+        assert!(is_incomplete("cat <<EOF\nabc\n"));
+        assert!(is_incomplete("echo a \\\n"));
+        assert!(is_incomplete("echo c\\\n"));
+        assert!(is_incomplete("echo c\\"));
+        assert!(!is_incomplete("echo 'abc'\n"));
+    }
+}
@@ -1,35 +1,194 @@
-mod instruction;
+mod arithmetic;
+mod ast;
+mod fd_table;
+mod glob;
+mod jobs;
+mod lexer;
+mod parser;
+mod script;
 mod session;
+mod status;
 mod text_processing;
 mod user;
 mod utils;
 
+use std::{
+    env,
+    io::{self, IsTerminal},
+    process,
+};
+
 use session::Session;
+use status::ExitStatus;
 use user::{Input, Output};
+use utils::UnbufferedStdin;
+
+const USAGE: &str =
+    "Usage: my_shell [-ils] [--norc] [-c commands [name [args...]] | script [args...]]";
+
+//Options given on the command line, e.g. my_shell -c 'echo hi'
+#[derive(Default)]
+struct Options {
+    command: bool,     //-c, the first argument holds the commands to run
+    stdin: bool,       //-s, commands are read from stdin even if arguments are given
+    interactive: bool, //-i, commands are prompted for even if stdin isn't a terminal
+    login: bool,       //-l, the profile is run on startup
+    norc: bool,        //--norc, the rc file isn't run on startup
+    args: Vec<String>, //Arguments following the options
+}
+
+impl Options {
+    //Parses the options in front of the first argument which isn't one (or '--')
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        //Programs like login start login shells with a name starting with '-', e.g. -my_shell
+        let mut options = Options {
+            login: args.next().is_some_and(|name| name.starts_with('-')),
+            ..Options::default()
+        };
+        for arg in args.by_ref() {
+            match arg.as_str() {
+                "--" => break,
+                "--login" => options.login = true,
+                "--norc" => options.norc = true,
+                _ if arg.starts_with("--") => return Err(format!("{}: invalid option", arg)),
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    for flag in arg.chars().skip(1) {
+                        match flag {
+                            'c' => options.command = true,
+                            's' => options.stdin = true,
+                            'i' => options.interactive = true,
+                            'l' => options.login = true,
+                            _ => return Err(format!("-{}: invalid option", flag)),
+                        }
+                    }
+                }
+                _ => {
+                    options.args.push(arg);
+                    break;
+                }
+            }
+        }
+        options.args.extend(args);
+        Ok(options)
+    }
+}
 
 fn main() {
+    let options = match Options::parse(env::args()) {
+        Ok(options) => options,
+        Err(msg) => {
+            Output::shell_error(format!("{}\n{}", msg, USAGE).into());
+            process::exit(2);
+        }
+    };
     let mut session = Session::new();
+    let status = run(&mut session, options);
+    process::exit(status.code());
+}
+
+/*
+Runs the commands of a -c option, of a script or from stdin, which are prompted for
+if stdin is a terminal (or with -i). Login shells run the profile first and interactive
+ones the rc file. Returns the status the shell exits with.
+*/
+fn run(session: &mut Session, mut options: Options) -> ExitStatus {
+    let terminal = io::stdin().is_terminal();
+    let reads_stdin = !options.command && (options.stdin || options.args.is_empty());
+    let interactive = reads_stdin && (options.interactive || terminal);
+    //Before the startup files, so that the programs they run can use the terminal as well
+    if interactive && terminal {
+        session.enable_job_control();
+    }
+    if options.login {
+        run_startup_file(session, "profile");
+    }
+    if interactive && !options.norc {
+        run_startup_file(session, "rc");
+    }
+    if session.exit_requested() {
+        return session.status;
+    }
+
+    if options.command {
+        //my_shell -c commands name args..., name becoming $0
+        if options.args.is_empty() {
+            Output::shell_error("-c: option requires an argument".into());
+            return ExitStatus::Code(2);
+        }
+        let commands = options.args.remove(0);
+        if !options.args.is_empty() {
+            let name = options.args.remove(0);
+            session.set_arguments(name, options.args);
+        }
+        return script::run(session, commands.as_bytes(), "-c");
+    }
+    if !options.stdin && !options.args.is_empty() {
+        let path = options.args.remove(0);
+        return script::run_file(session, &path, &options.args);
+    }
+
+    //With -s, the arguments are positional parameters, while $0 stays the shell's name
+    let name = session.get_var("0").unwrap_or_default();
+    if !options.args.is_empty() {
+        session.set_arguments(name.clone(), options.args);
+    }
+    if interactive {
+        run_interactive(session, terminal)
+    } else {
+        script::run(session, UnbufferedStdin::new(), &name)
+    }
+}
+
+//Runs a file of the config directory (e.g. ~/.config/myshell/rc), if it exists
+fn run_startup_file(session: &mut Session, name: &str) {
+    //Without a home directory there's no file to run either
+    if let Ok(dir) = utils::config_path() {
+        script::run_startup_file(session, &dir.join(name));
+    }
+}
+
+/*
+Prompts for commands until exit is run or the input ends. Lines are edited with the
+prompt's line editor and jobs are controlled if stdin is a terminal, otherwise (e.g. with
+-i and a pipe) lines are taken as they are.
+*/
+fn run_interactive(session: &mut Session, terminal: bool) -> ExitStatus {
     let mut input_control = Input::new();
     loop {
-        let input_result = input_control.prompt(&session.exit_code, &session.cwd);
-        if let Err(e) = input_result {
-            eprintln!("ERROR: {}", e);
-            break;
-        }
-        let input = input_result.unwrap();
-        if input.trim() == "exit" {
-            println!("Goodbye.");
-            break;
-        } else if input.trim().len() == 0 {
-            session.exit_code = 0.to_string();
+        session.notify_job_changes();
+        let prompt = session
+            .get_var("PS1")
+            .unwrap_or_else(|| Input::default_prompt(&session.cwd));
+        let continuation_prompt = session.get_var("PS2").unwrap_or_else(|| "> ".to_string());
+        let input_result = if terminal {
+            input_control
+                .prompt(&session.status, &prompt, &continuation_prompt)
+                .map(Some)
+        } else {
+            input_control.prompt_plain(&prompt, &continuation_prompt)
+        };
+        let input = match input_result {
+            Ok(Some(input)) => input,
+            //The input has ended
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                break;
+            }
+        };
+        if input.trim().is_empty() {
+            session.status = ExitStatus::SUCCESS;
             continue;
         }
 
         if let Err(msg) = session.execute_input(&input, false) {
-            Output::shell_error(&mut input_control.stdout, msg);
-        } else {
-            session.exit_code = "0".to_string();
+            Output::shell_error(msg);
         }
         input_control.input.clear();
+        if session.exit_requested() {
+            println!("Goodbye.");
+            break;
+        }
     }
+    session.status
 }
@@ -0,0 +1,771 @@
+use std::{error::Error, rc::Rc};
+
+use crate::{
+    ast::{
+        AndOrList, Assignment, CaseItem, Command, CompoundCommand, Connector, FunctionDefinition,
+        List, Pipeline, Redirection, RedirectionKind, SimpleCommand, Word, WordPart,
+    },
+    lexer::{self, IncompleteInput, Operator, Token},
+};
+
+/*
+Builds the syntax tree from the tokens produced by the lexer.
+
+Grammar (simplified):
+    list     := and_or ((';' | '&' | NEWLINE) and_or)* [';' | '&']
+    and_or   := pipeline (('&&' | '||') NEWLINE* pipeline)*
+    pipeline := '!'* command ('|' command)*
+    command  := (ASSIGNMENT | WORD | redirection)+   (assignments only in front of the first WORD)
+              | '((' expression '))' redirection*
+              | '(' list ')' redirection*
+              | '{' list '}' redirection*            (reserved words like '{' only where a command starts)
+              | 'if' list 'then' list ('elif' list 'then' list)* ['else' list] 'fi' redirection*
+              | ('while' | 'until') list 'do' list 'done' redirection*
+              | 'for' NAME [NEWLINE* 'in' WORD*] [';' | NEWLINE] NEWLINE* 'do' list 'done' redirection*
+              | 'case' WORD NEWLINE* 'in' (case_item ';;')* [case_item] NEWLINE* 'esac' redirection*
+              | NAME '(' ')' NEWLINE* command            (a function definition, command being a compound one)
+    case_item := NEWLINE* ['('] WORD ('|' WORD)* ')' list
+    redirection := [IO_NUMBER] ('>' | '>>' | '<' | '<<<' | '<<' | '<<-' | '>&' | '<&' | '&>' | '&>>') WORD
+*/
+pub fn parse(tokens: Vec<Token>) -> Result<List, Box<dyn Error>> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let list = parser.parse_list()?;
+    //Anything left over (e.g. a ')' without a '(') can't continue the list
+    if parser.peek().is_some() {
+        return Err(parser.unexpected_token(""));
+    }
+    Ok(list)
+}
+
+//Reserved words which end a list, e.g. the '}' of a group
+const CLOSING_WORDS: [&str; 8] = ["}", "then", "elif", "else", "fi", "do", "done", "esac"];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize, //Index of the next token to be consumed
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_list(&mut self) -> Result<List, Box<dyn Error>> {
+        let mut list = List {
+            and_or_lists: Vec::new(),
+        };
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() {
+                break;
+            }
+            let mut and_or = self.parse_and_or()?;
+            and_or.background = matches!(self.peek(), Some(Token::Op(Operator::Amp)));
+            list.and_or_lists.push(and_or);
+            match self.peek() {
+                Some(Token::Op(Operator::Semi | Operator::Amp)) | Some(Token::Newline) => {
+                    self.pos += 1
+                }
+                _ => break,
+            }
+        }
+        Ok(list)
+    }
+
+    //Whether the current token ends a list, e.g. the ')' of a subshell
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None | Some(Token::Op(Operator::RParen | Operator::DSemi)) => true,
+            _ => CLOSING_WORDS.iter().any(|word| self.at_reserved_word(word)),
+        }
+    }
+
+    //Whether the current token is the given reserved word, which must be unquoted
+    fn at_reserved_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(parts))
+            if matches!(&parts[..], [WordPart::Literal(text)] if text == word))
+    }
+
+    //Consumes the given reserved word, which has to be the current token
+    fn expect_reserved_word(&mut self, word: &str) -> Result<(), Box<dyn Error>> {
+        if !self.at_reserved_word(word) {
+            return Err(self.unexpected_token(word));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /*
+    The error for a token which can't appear at the current position. If the input
+    has ended instead, more of it may follow, e.g. the 'done' of a loop on the next line.
+    */
+    fn unexpected_token(&self, expected: &str) -> Box<dyn Error> {
+        //Reserved words are named, unlike other words
+        let closing = CLOSING_WORDS
+            .iter()
+            .find(|word| self.at_reserved_word(word));
+        match self.peek() {
+            Some(_) if closing.is_some() => {
+                let word = closing.unwrap_or(&"");
+                format!("Syntax error near unexpected token '{}'", word).into()
+            }
+            Some(token) => format!("Syntax error near unexpected token {}", token).into(),
+            None => Box::new(IncompleteInput(format!("missing '{}'", expected))),
+        }
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOrList, Box<dyn Error>> {
+        let mut and_or = AndOrList {
+            first: self.parse_pipeline()?,
+            rest: Vec::new(),
+            background: false,
+        };
+        loop {
+            let (connector, op) = match self.peek() {
+                Some(Token::Op(op @ Operator::AndIf)) => (Connector::And, *op),
+                Some(Token::Op(op @ Operator::OrIf)) => (Connector::Or, *op),
+                _ => break,
+            };
+            self.pos += 1;
+            //The next pipeline may follow on a new line
+            self.skip_newlines();
+            self.expect_continuation(op)?;
+            and_or.rest.push((connector, self.parse_pipeline()?));
+        }
+        Ok(and_or)
+    }
+
+    //Operators like | and && need another command after them, which may be on a line still to be entered
+    fn expect_continuation(&self, op: Operator) -> Result<(), Box<dyn Error>> {
+        if self.peek().is_none() {
+            let msg = format!("command expected after '{}'", op.as_str());
+            return Err(Box::new(IncompleteInput(msg)));
+        }
+        Ok(())
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(Token::Newline) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, Box<dyn Error>> {
+        //Like in bash, every further ! negates the pipeline once more
+        let mut negated = false;
+        while self.at_reserved_word("!") {
+            negated = !negated;
+            self.pos += 1;
+        }
+        let mut pipeline = Pipeline {
+            commands: vec![self.parse_command()?],
+            negated,
+        };
+        while let Some(Token::Op(Operator::Pipe)) = self.peek() {
+            self.pos += 1;
+            self.skip_newlines();
+            self.expect_continuation(Operator::Pipe)?;
+            pipeline.commands.push(self.parse_command()?);
+        }
+        Ok(pipeline)
+    }
+
+    fn parse_command(&mut self) -> Result<Command, Box<dyn Error>> {
+        let compound = if let Some(Token::Op(Operator::LParen)) = self.peek() {
+            self.pos += 1;
+            CompoundCommand::Subshell(self.parse_compound_body(")")?)
+        } else if self.at_reserved_word("{") {
+            self.pos += 1;
+            CompoundCommand::Group(self.parse_compound_body("}")?)
+        } else if self.at_reserved_word("if") {
+            self.pos += 1;
+            self.parse_if()?
+        } else if self.at_reserved_word("while") || self.at_reserved_word("until") {
+            let until = self.at_reserved_word("until");
+            self.pos += 1;
+            let condition = self.parse_clause(&["do"])?.0;
+            let body = self.parse_compound_body("done")?;
+            CompoundCommand::While {
+                condition,
+                body,
+                until,
+            }
+        } else if self.at_reserved_word("for") {
+            self.pos += 1;
+            self.parse_for()?
+        } else if self.at_reserved_word("case") {
+            self.pos += 1;
+            self.parse_case()?
+        } else if let Some(name) = self.function_name() {
+            self.pos += 3;
+            return self.parse_function_definition(name);
+        } else {
+            return Ok(Command::Simple(self.parse_simple_command()?));
+        };
+        let mut redirections = Vec::new();
+        while let Some(redirection) = self.parse_redirection()? {
+            redirections.extend(redirection);
+        }
+        Ok(Command::Compound(compound, redirections))
+    }
+
+    //Parses the list within a compound command, up to and including the closing ')' or '}' (or e.g. 'fi')
+    fn parse_compound_body(&mut self, closing: &'static str) -> Result<List, Box<dyn Error>> {
+        Ok(self.parse_clause(&[closing])?.0)
+    }
+
+    /*
+    Parses a list which mustn't be empty, up to and including the first of the reserved
+    words (or ')') in closing, and returns the list along with the word which ended it.
+    */
+    fn parse_clause(
+        &mut self,
+        closing: &[&'static str],
+    ) -> Result<(List, &'static str), Box<dyn Error>> {
+        let body = self.parse_list()?;
+        let closed = closing.iter().find(|word| match self.peek() {
+            Some(Token::Op(Operator::RParen)) => **word == ")",
+            _ => self.at_reserved_word(word),
+        });
+        match closed {
+            Some(word) if !body.and_or_lists.is_empty() => {
+                self.pos += 1;
+                Ok((body, word))
+            }
+            _ => Err(self.unexpected_token(closing[closing.len() - 1])),
+        }
+    }
+
+    //The name of the function whose definition starts at the current position, if there is one
+    fn function_name(&self) -> Option<String> {
+        let Some(Token::Word(word)) = self.peek() else {
+            return None;
+        };
+        let [WordPart::Literal(name)] = &word[..] else {
+            return None;
+        };
+        let parentheses = (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2));
+        match parentheses {
+            (Some(Token::Op(Operator::LParen)), Some(Token::Op(Operator::RParen))) => {
+                Some(name.clone())
+            }
+            _ => None,
+        }
+    }
+
+    //Parses the body of a function definition following the name and '()'
+    fn parse_function_definition(&mut self, name: String) -> Result<Command, Box<dyn Error>> {
+        self.skip_newlines();
+        if self.peek().is_none() {
+            return Err(Box::new(IncompleteInput(
+                "missing function body".to_string(),
+            )));
+        }
+        let start = self.pos;
+        match self.parse_command()? {
+            Command::Compound(body, redirections) => {
+                Ok(Command::Function(Rc::new(FunctionDefinition {
+                    name,
+                    body,
+                    redirections,
+                })))
+            }
+            _ => {
+                self.pos = start;
+                Err(self.unexpected_token("{"))
+            }
+        }
+    }
+
+    //Parses an if command following the 'if'
+    fn parse_if(&mut self) -> Result<CompoundCommand, Box<dyn Error>> {
+        let mut branches = Vec::new();
+        let mut else_body = None;
+        loop {
+            let condition = self.parse_clause(&["then"])?.0;
+            let (body, closing) = self.parse_clause(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+            match closing {
+                "elif" => continue,
+                "else" => else_body = Some(self.parse_compound_body("fi")?),
+                _ => {}
+            }
+            break;
+        }
+        Ok(CompoundCommand::If {
+            branches,
+            else_body,
+        })
+    }
+
+    //Parses a for loop following the 'for'
+    fn parse_for(&mut self) -> Result<CompoundCommand, Box<dyn Error>> {
+        let variable = match self.peek() {
+            Some(Token::Word(word)) => match &word[..] {
+                [WordPart::Literal(name)] if lexer::is_variable_name(name) => name.clone(),
+                _ => {
+                    let name: String = word.iter().map(WordPart::to_string).collect();
+                    return Err(format!("for: '{}' isn't a valid variable name", name).into());
+                }
+            },
+            _ => return Err(self.unexpected_token("do")),
+        };
+        self.pos += 1;
+
+        let mut words = None;
+        self.skip_newlines();
+        if self.at_reserved_word("in") {
+            self.pos += 1;
+            let mut list = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                list.push(word.clone());
+                self.pos += 1;
+            }
+            words = Some(list);
+        }
+        if let Some(Token::Op(Operator::Semi)) | Some(Token::Newline) = self.peek() {
+            self.pos += 1;
+        }
+        self.skip_newlines();
+        self.expect_reserved_word("do")?;
+        let body = self.parse_compound_body("done")?;
+        Ok(CompoundCommand::For {
+            variable,
+            words,
+            body,
+        })
+    }
+
+    //Parses a case command following the 'case'
+    fn parse_case(&mut self) -> Result<CompoundCommand, Box<dyn Error>> {
+        let Some(Token::Word(word)) = self.peek().cloned() else {
+            return Err(self.unexpected_token("esac"));
+        };
+        self.pos += 1;
+        self.skip_newlines();
+        self.expect_reserved_word("in")?;
+
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_reserved_word("esac") {
+                self.pos += 1;
+                break;
+            }
+            if let Some(Token::Op(Operator::LParen)) = self.peek() {
+                self.pos += 1;
+            }
+            //Patterns separated by '|', up to the ')'
+            let mut patterns = Vec::new();
+            loop {
+                let Some(Token::Word(pattern)) = self.peek().cloned() else {
+                    return Err(self.unexpected_token(")"));
+                };
+                patterns.push(pattern);
+                self.pos += 1;
+                match self.next() {
+                    Some(Token::Op(Operator::Pipe)) => {}
+                    Some(Token::Op(Operator::RParen)) => break,
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.unexpected_token(")"));
+                    }
+                }
+            }
+            //The body may be empty, and the ';;' can be left out after the last item
+            let body = self.parse_list()?;
+            items.push(CaseItem { patterns, body });
+            match self.peek() {
+                Some(Token::Op(Operator::DSemi)) => self.pos += 1,
+                _ if self.at_reserved_word("esac") => {}
+                _ => return Err(self.unexpected_token("esac")),
+            }
+        }
+        Ok(CompoundCommand::Case { word, items })
+    }
+
+    //Splits a word of the form NAME=value into an assignment
+    fn assignment(word: &Word) -> Option<Assignment> {
+        let Some(WordPart::Literal(first)) = word.first() else {
+            return None;
+        };
+        let (name, value_start) = first.split_once('=')?;
+        if !lexer::is_variable_name(name) {
+            return None;
+        }
+        let mut value = word[1..].to_vec();
+        if !value_start.is_empty() {
+            value.insert(0, WordPart::Literal(value_start.to_string()));
+        }
+        Some(Assignment {
+            name: name.to_string(),
+            value,
+        })
+    }
+
+    fn redirection_kind(op: Operator) -> Option<RedirectionKind> {
+        match op {
+            Operator::Great | Operator::AndGreat => Some(RedirectionKind::Overwrite),
+            Operator::DGreat | Operator::AndDGreat => Some(RedirectionKind::Append),
+            Operator::Less => Some(RedirectionKind::Input),
+            Operator::TLess => Some(RedirectionKind::HereString),
+            Operator::DLess | Operator::DLessDash => Some(RedirectionKind::HereDoc),
+            Operator::GreatAnd | Operator::LessAnd => Some(RedirectionKind::Duplicate),
+            _ => None,
+        }
+    }
+
+    /*
+    Turns a redirection operator with its (optional) file descriptor and target into
+    redirections. &>file is shorthand for >file 2>&1 and results in two of them.
+    */
+    fn build_redirections(fd: Option<u32>, op: Operator, target: Word) -> Vec<Redirection> {
+        //Unwrap() will not panic, since op has been checked to be a redirection operator
+        let kind = Self::redirection_kind(op).unwrap();
+        let reads = matches!(
+            op,
+            Operator::Less
+                | Operator::TLess
+                | Operator::LessAnd
+                | Operator::DLess
+                | Operator::DLessDash
+        );
+        let mut redirections = vec![Redirection {
+            fd: fd.unwrap_or(if reads { 0 } else { 1 }),
+            kind,
+            target,
+        }];
+        if let Operator::AndGreat | Operator::AndDGreat = op {
+            redirections.push(Redirection {
+                fd: 2,
+                kind: RedirectionKind::Duplicate,
+                target: vec![WordPart::Literal("1".to_string())],
+            });
+        }
+        redirections
+    }
+
+    //Parses the redirection at the current position, or returns None if there isn't one
+    fn parse_redirection(&mut self) -> Result<Option<Vec<Redirection>>, Box<dyn Error>> {
+        let fd = match self.peek() {
+            Some(Token::IoNumber(number)) => Some(*number),
+            _ => None,
+        };
+        let op_index = if fd.is_some() { self.pos + 1 } else { self.pos };
+        let op = match self.tokens.get(op_index) {
+            Some(Token::Op(op)) if Self::redirection_kind(*op).is_some() => *op,
+            _ if fd.is_none() => return Ok(None),
+            Some(token) => {
+                return Err(format!("Syntax error near unexpected token {}", token).into())
+            }
+            None => return Err("Syntax error: unexpected end of input".into()),
+        };
+        self.pos = op_index + 1;
+        let target = match self.next() {
+            Some(Token::Word(target)) => target,
+            _ => return Err(format!("Please specify a target after '{}'", op.as_str()).into()),
+        };
+        Ok(Some(Self::build_redirections(fd, op, target)))
+    }
+
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, Box<dyn Error>> {
+        let mut command = SimpleCommand {
+            assignments: Vec::new(),
+            words: Vec::new(),
+            redirections: Vec::new(),
+        };
+        //((expression)) is the same as let "expression", but may only be followed by redirections
+        let arithmetic = matches!(self.peek(), Some(Token::Arithmetic(_)));
+        if let Some(Token::Arithmetic(expression)) = self.peek() {
+            command.words = vec![
+                vec![WordPart::Literal("let".to_string())],
+                vec![WordPart::DoubleQuoted(expression.clone())],
+            ];
+            self.pos += 1;
+        }
+        loop {
+            match self.peek() {
+                Some(Token::Word(_)) if !arithmetic => {
+                    if let Some(Token::Word(word)) = self.next() {
+                        //Assignments are only recognized in front of the program
+                        match Self::assignment(&word) {
+                            Some(assignment) if command.words.is_empty() => {
+                                command.assignments.push(assignment)
+                            }
+                            _ => command.words.push(word),
+                        }
+                    }
+                }
+                Some(Token::IoNumber(_)) | Some(Token::Op(_)) => match self.parse_redirection()? {
+                    Some(redirections) => command.redirections.extend(redirections),
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+
+        if command.words.is_empty()
+            && command.redirections.is_empty()
+            && command.assignments.is_empty()
+        {
+            return Err(match self.peek() {
+                Some(token) => format!("Syntax error near unexpected token {}", token).into(),
+                None => "Syntax error: unexpected end of input".into(),
+            });
+        }
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text_processing::parse_input;
+
+    fn literal(text: &str) -> Word {
+        vec![WordPart::Literal(text.to_string())]
+    }
+
+    //The only command of the input
+    fn command(input: &str) -> Command {
+        let mut list = parse_input(input).unwrap();
+        assert_eq!(list.and_or_lists.len(), 1);
+        let and_or = list.and_or_lists.remove(0);
+        assert!(and_or.rest.is_empty());
+        let mut commands = and_or.first.commands;
+        assert_eq!(commands.len(), 1);
+        commands.remove(0)
+    }
+
+    fn simple_command(input: &str) -> SimpleCommand {
+        match command(input) {
+            Command::Simple(command) => command,
+            other => panic!("{:?} isn't a simple command", other),
+        }
+    }
+
+    fn error(input: &str) -> Box<dyn Error> {
+        parse_input(input).unwrap_err()
+    }
+
+    //Checks that input is displayed as written and parses to the same tree again
+    fn assert_round_trip(input: &str) {
+        let list = parse_input(input).unwrap();
+        assert_eq!(list.to_string(), input);
+        assert_eq!(parse_input(&list.to_string()).unwrap(), list);
+    }
+
+    #[test]
+    fn parses_lists_and_pipelines() {
+        let list = parse_input("a | b && c || d & e; f").unwrap();
+        assert_eq!(list.and_or_lists.len(), 3);
+        let first = &list.and_or_lists[0];
+        assert_eq!(first.first.commands.len(), 2);
+        let connectors: Vec<_> = first.rest.iter().map(|(c, _)| c.clone()).collect();
+        assert_eq!(connectors, [Connector::And, Connector::Or]);
+        //'&' puts the whole AND-OR list in the background
+        assert!(first.background);
+        assert!(!list.and_or_lists[1].background);
+    }
+
+    #[test]
+    fn separates_assignments_words_and_redirections() {
+        let command = simple_command("x=1 y= cmd z=2 2>&1 >out");
+        let names: Vec<_> = command.assignments.iter().map(|a| &a.name[..]).collect();
+        assert_eq!(names, ["x", "y"]);
+        assert_eq!(command.assignments[1].value, Word::new());
+        //Assignments after the program's name are arguments
+        assert_eq!(command.words, [literal("cmd"), literal("z=2")]);
+        assert_eq!(
+            command.redirections,
+            [
+                Redirection {
+                    fd: 2,
+                    kind: RedirectionKind::Duplicate,
+                    target: literal("1"),
+                },
+                Redirection {
+                    fd: 1,
+                    kind: RedirectionKind::Overwrite,
+                    target: literal("out"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn turns_heredocs_into_redirections() {
+        let command = simple_command("cat <<EOF\nhi\nEOF\n");
+        assert_eq!(command.redirections.len(), 1);
+        assert_eq!(command.redirections[0].fd, 0);
+        assert_eq!(command.redirections[0].kind, RedirectionKind::HereDoc);
+        assert_eq!(
+            command.redirections[0].target,
+            [WordPart::DoubleQuoted(vec![WordPart::Literal(
+                "hi\n".to_string()
+            )])]
+        );
+    }
+
+    #[test]
+    fn reserved_words_are_ordinary_elsewhere() {
+        assert_eq!(simple_command("echo if then fi {").words.len(), 5);
+        //Quoting a reserved word makes it an ordinary one
+        assert!(matches!(command("'if' a"), Command::Simple(_)));
+    }
+
+    #[test]
+    fn turns_arithmetic_commands_into_let() {
+        let command = simple_command("((x += 1))");
+        assert_eq!(command.words[0], literal("let"));
+        assert_eq!(command.words.len(), 2);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for input in [
+            "a | b && c || d & e; f",
+            "x=1 y=2 cmd arg 2>&1 >out <<<hs",
+            "echo $((x * 2)) $(whoami) \"${a:-b}\" ${#x} ${y%.*} ${z//a/b} 'q' e' 'f",
+        ] {
+            assert_round_trip(input);
+        }
+        //&> is written out as the two redirections it stands for
+        let list = parse_input("cmd &>>log").unwrap();
+        assert_eq!(list.to_string(), "cmd >>log 2>&1");
+        assert_eq!(parse_input(&list.to_string()).unwrap(), list);
+    }
+
+    #[test]
+    fn reports_incomplete_input() {
+        for input in [
+            "a &&",
+            "a |",
+            "if a; then b",
+            "while a; do",
+            "case x in",
+            "{ a;",
+        ] {
+            assert!(error(input).is::<IncompleteInput>(), "{}", input);
+        }
+        for input in ["fi", "echo )", "a && && b", "for 1 in a; do b; done"] {
+            assert!(!error(input).is::<IncompleteInput>(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_subshells_and_groups() {
+        let Command::Compound(CompoundCommand::Subshell(body), redirections) =
+            command("(cd /; ls) >out")
+        else {
+            panic!("not a subshell");
+        };
+        assert_eq!(body.and_or_lists.len(), 2);
+        assert_eq!(redirections.len(), 1);
+        assert!(matches!(
+            command("{ cd /; ls; }"),
+            Command::Compound(CompoundCommand::Group(_), _)
+        ));
+        //Unlike parentheses, braces are only reserved words where a command starts
+        assert!(parse_input("{ ls }").is_err());
+        assert_round_trip("(cd /; ls) | wc");
+        assert_round_trip("{ cd /; ls; } >out");
+    }
+
+    #[test]
+    fn parses_compound_commands() {
+        let Command::Compound(
+            CompoundCommand::If {
+                branches,
+                else_body,
+            },
+            _,
+        ) = command("if a; then b; elif c; then d; else e; fi")
+        else {
+            panic!("not an if");
+        };
+        assert_eq!(branches.len(), 2);
+        assert!(else_body.is_some());
+
+        let Command::Compound(CompoundCommand::While { until, .. }, _) =
+            command("until a; do b; done")
+        else {
+            panic!("not a loop");
+        };
+        assert!(until);
+
+        let Command::Compound(
+            CompoundCommand::For {
+                variable, words, ..
+            },
+            _,
+        ) = command("for i do :; done")
+        else {
+            panic!("not a for loop");
+        };
+        assert_eq!(variable, "i");
+        assert_eq!(words, None);
+
+        let Command::Compound(CompoundCommand::Case { items, .. }, _) =
+            command("case $x in\n(a|b) echo ab;;\n*) echo other\nesac")
+        else {
+            panic!("not a case command");
+        };
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].patterns, [literal("a"), literal("b")]);
+
+        for input in [
+            "if a; then b; elif c; then d; else e; fi",
+            "while a; do b; done; until a; do b; done",
+            "for i in 1 2; do echo $i; done; for j; do :; done",
+            "case $x in a|b) echo ab ;; *) echo other ;; esac",
+        ] {
+            assert_round_trip(input);
+        }
+    }
+
+    #[test]
+    fn parses_negated_pipelines() {
+        let list = parse_input("a | b && ! c; ! ! d").unwrap();
+        let first = &list.and_or_lists[0];
+        assert!(!first.first.negated);
+        assert!(first.rest[0].1.negated);
+        //Every further ! negates the pipeline once more
+        assert!(!list.and_or_lists[1].first.negated);
+        assert_round_trip("! a | b");
+    }
+
+    #[test]
+    fn reserved_words_start_compound_commands() {
+        assert!(matches!(
+            command("if a; then b; fi"),
+            Command::Compound(CompoundCommand::If { .. }, _)
+        ));
+        assert_eq!(
+            error("if then").to_string(),
+            "Syntax error near unexpected token 'then'"
+        );
+        assert_eq!(
+            error("done").to_string(),
+            "Syntax error near unexpected token 'done'"
+        );
+    }
+
+    #[test]
+    fn parses_function_definitions() {
+        let Command::Function(function) = command("f() { echo hi; } >log") else {
+            panic!("not a function definition");
+        };
+        assert_eq!(function.name, "f");
+        assert!(matches!(function.body, CompoundCommand::Group(_)));
+        assert_eq!(function.redirections.len(), 1);
+        assert!(parse_input("f() echo").is_err());
+        assert_round_trip("f() { echo hi; } >log");
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use crate::{session::Session, status::ExitStatus, text_processing, user::Output};
+
+/*
+Non-interactive execution, e.g. of a script given on the command line. The input is
+read line by line and run as soon as it forms complete commands, so that the commands
+in front of a syntax error have already run when it's found, like in other shells.
+*/
+
+//Exit status of a shell stopped by a syntax error
+const SYNTAX_ERROR: ExitStatus = ExitStatus::Code(2);
+
+/*
+Runs the script at path with args as its positional parameters and returns the status
+the shell should exit with. Scripts starting with #!/usr/bin/env my_shell are run this
+way by the system, since the line is a comment to the shell itself.
+*/
+pub fn run_file(session: &mut Session, path: &str, args: &[String]) -> ExitStatus {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            Output::shell_error(format!("{}: {}", path, err).into());
+            return match err.kind() {
+                io::ErrorKind::NotFound => ExitStatus::NotFound,
+                _ => ExitStatus::NotExecutable,
+            };
+        }
+    };
+    session.set_arguments(path.to_string(), args.to_vec());
+    run(session, BufReader::new(file), path)
+}
+
+/*
+Runs a startup file (e.g. ~/.config/myshell/rc) within the session, if it exists.
+Its errors are reported, but only stop the file itself rather than the shell.
+*/
+pub fn run_startup_file(session: &mut Session, path: &Path) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+        Err(err) => {
+            Output::shell_error(format!("{}: {}", path.display(), err).into());
+            return;
+        }
+    };
+    run(session, BufReader::new(file), &path.display().to_string());
+}
+
+/*
+Runs the commands read from input and returns the status the shell should exit with,
+i.e. the one of the last command or the one given to exit. Syntax errors are reported
+along with name and the number of the line they were found on, e.g. "build.sh:12: ...",
+and stop the execution.
+*/
+pub fn run(session: &mut Session, input: impl BufRead, name: &str) -> ExitStatus {
+    let mut source = String::new();
+    let mut line_number = 0;
+    for line in input.lines() {
+        line_number += 1;
+        match line {
+            Ok(line) => source.push_str(&line),
+            Err(err) => {
+                Output::shell_error(format!("{}:{}: {}", name, line_number, err).into());
+                return ExitStatus::BuiltinError;
+            }
+        }
+        source.push('\n');
+        if text_processing::is_incomplete(&source) {
+            continue;
+        }
+        let result = session.execute_input(&source, false);
+        source.clear();
+        if let Err(msg) = result {
+            Output::shell_error(format!("{}:{}: {}", name, line_number, msg).into());
+            return SYNTAX_ERROR;
+        }
+        if session.exit_requested() {
+            break;
+        }
+    }
+    //The input may have ended in the middle of a command, e.g. before the 'fi' of an if
+    if let Err(msg) = text_processing::parse_input(&source) {
+        Output::shell_error(format!("{}:{}: {}", name, line_number, msg).into());
+        return SYNTAX_ERROR;
+    }
+    session.status
+}
@@ -1,42 +1,234 @@
 use std::{
+    collections::{HashMap, HashSet},
     env,
     error::Error,
-    io::{Read, Write},
+    fmt,
+    fs::File,
+    io::{self, Read, Write},
+    mem,
+    os::unix::process::CommandExt,
     path::{Path, PathBuf},
-    process::{Child, Command},
+    process,
+    rc::Rc,
+    thread,
 };
 
 use crate::{
-    instruction::{Instruction, StdoutTo},
-    text_processing, utils,
+    arithmetic,
+    ast::{
+        AndOrList, Command, CompoundCommand, Connector, FunctionDefinition, List, Pipeline,
+        Redirection, RedirectionKind, SimpleCommand,
+    },
+    fd_table::FdTable,
+    glob,
+    jobs::{self, Job, JobState, JobTable},
+    lexer,
+    status::ExitStatus,
+    text_processing,
+    user::Output,
+    utils,
 };
 
-use os_pipe::{self, PipeWriter};
+use os_pipe::{self, PipeReader};
+
+//A shell variable. Exported ones are passed on to the environment of spawned processes.
+#[derive(Clone)]
+pub struct Variable {
+    pub value: String,
+    pub exported: bool,
+}
+
+//Returned by builtins which fail without an error message, e.g. let 0. Holds the exit status.
+#[derive(Debug)]
+struct Failure(ExitStatus);
+
+impl fmt::Display for Failure {
+    fn fmt(&self, _: &mut fmt::Formatter) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl Error for Failure {}
+
+/*
+Skips the commands following the current one: break and continue leave the given
+number of enclosing loops (continue resuming the outermost of them), return leaves
+the function being called with the given status, an abort skips the rest of
+the input (e.g. after Ctrl-C) and exit ends the shell itself.
+*/
+#[derive(Clone, Copy, PartialEq)]
+enum Jump {
+    Break(usize),
+    Continue(usize),
+    Return(ExitStatus),
+    Abort,
+    Exit,
+}
+
+//What running a simple command resulted in
+enum Outcome {
+    Finished(ExitStatus), //Exit status of a builtin (or of assignments only)
+    Spawned(i32),         //Process ID of a program which is still running
+}
 
 pub struct Session {
-    pub cwd: PathBuf,        //Current working directory
-    pub exit_code: String,   //Status of last executed program
-    dir_stack: Vec<PathBuf>, //For pushd/ popd
+    pub cwd: PathBuf,                      //Current working directory
+    pub status: ExitStatus,                //Status of last executed program
+    dir_stack: Vec<PathBuf>,               //For pushd/ popd
+    vars: HashMap<String, Variable>,       //Shell variables, including the environment
+    positional_parameters: Vec<String>,    //$1, $2, ...
+    shell_name: String,                    //$0
+    last_background_pid: Option<u32>,      //$!
+    pid: u32,                              //$$, the shell's own process even within subshells
+    options: HashSet<&'static str>,        //Enabled options (see OPTIONS)
+    fds: FdTable, //Descriptors all commands start out with, e.g. set up by the redirections of a group
+    pipe_status: Vec<ExitStatus>, //Exit statuses of the commands of the last pipeline, ${PIPESTATUS[@]}
+    jobs: JobTable,               //Commands started in the background with '&' or stopped by Ctrl-Z
+    job_control: bool, //Whether pipelines get the terminal while they run and can be stopped
+    terminal_modes: Option<libc::termios>, //Settings of the terminal, restored after each job
+    loop_depth: usize, //Number of loops currently running, which break and continue can leave
+    jump: Option<Jump>, //Set while commands are being skipped, e.g. after break
+    functions: HashMap<String, Rc<FunctionDefinition>>,
+    local_scopes: Vec<Vec<(String, Option<Variable>)>>, //For each function being called, the previous values of its local variables
 }
 
 impl Session {
-    const BUILTINS: [&'static str; 5] = ["cd", "pwd", "pushd", "popd", "history"];
+    const BUILTINS: [&'static str; 22] = [
+        "cd", "pwd", "pushd", "popd", "history", "export", "unset", "set", "let", "jobs", "fg",
+        "bg", "disown", "wait", "break", "continue", "local", "return", "exit", ":", "true",
+        "false",
+    ];
+    //How deeply function calls may be nested, so that endless recursion fails rather than crashing the shell
+    const MAX_FUNCTION_DEPTH: usize = 200;
+    //Options which can be enabled with 'set -o name' and disabled with 'set +o name'
+    const OPTIONS: [&'static str; 6] = [
+        "legacy_substitution", //${cmd} substitutes the output of cmd, unless cmd is a variable
+        "nullglob",            //Patterns without any matching paths are removed
+        "failglob",            //Patterns without any matching paths are an error
+        "dotglob",             //Wildcards match names starting with '.'
+        "globstar",            //** matches files and directories recursively
+        "pipefail",            //Pipelines fail if any of their commands does, not just the last one
+    ];
     pub fn new() -> Self {
+        //Variables inherited from the environment stay exported
+        let vars = env::vars()
+            .map(|(name, value)| {
+                let exported = true;
+                (name, Variable { value, exported })
+            })
+            .collect();
         Session {
-            cwd: env::current_dir().unwrap_or(PathBuf::new()),
-            exit_code: String::from("0"),
+            cwd: env::current_dir().unwrap_or_default(),
+            status: ExitStatus::SUCCESS,
             dir_stack: vec![],
+            vars,
+            positional_parameters: Vec::new(),
+            shell_name: env::args().next().unwrap_or(String::from("my_shell")),
+            last_background_pid: None,
+            pid: process::id(),
+            options: HashSet::new(),
+            fds: FdTable::new(),
+            pipe_status: vec![ExitStatus::SUCCESS],
+            jobs: JobTable::new(),
+            job_control: false,
+            terminal_modes: None,
+            loop_depth: 0,
+            jump: None,
+            functions: HashMap::new(),
+            local_scopes: Vec::new(),
+        }
+    }
+
+    pub fn option_enabled(&self, name: &str) -> bool {
+        self.options.contains(name)
+    }
+
+    /*
+    Returns the value of a variable or special parameter ($?, $$, $!, $#, $0, $1...),
+    or None if it isn't set. $@ and $* are handled by the caller using
+    positional_parameters().
+    */
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "!" => self.last_background_pid.map(|pid| pid.to_string()),
+            "#" => Some(self.positional_parameters.len().to_string()),
+            "0" => Some(self.shell_name.clone()),
+            "PIPESTATUS" => self.pipe_status.first().map(ExitStatus::to_string),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                //${00} and the like name $0 as well
+                let index: usize = name.parse().ok()?;
+                match index.checked_sub(1) {
+                    Some(index) => self.positional_parameters.get(index).cloned(),
+                    None => Some(self.shell_name.clone()),
+                }
+            }
+            _ => self.vars.get(name).map(|variable| variable.value.clone()),
+        }
+    }
+
+    //Sets a variable, keeping it exported if it already was
+    pub fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(variable) => variable.value = value,
+            None => {
+                let exported = false;
+                self.vars
+                    .insert(name.to_string(), Variable { value, exported });
+            }
+        }
+    }
+
+    //Returns the elements of an array, which is a single one for other variables
+    pub fn get_array(&self, name: &str) -> Option<Vec<String>> {
+        match name {
+            "PIPESTATUS" => Some(self.pipe_status.iter().map(ExitStatus::to_string).collect()),
+            name => self.get_var(name).map(|value| vec![value]),
+        }
+    }
+
+    pub fn positional_parameters(&self) -> &[String] {
+        &self.positional_parameters
+    }
+
+    //Sets $0 and the positional parameters, e.g. to the path and arguments of a script
+    pub fn set_arguments(&mut self, name: String, parameters: Vec<String>) {
+        self.shell_name = name;
+        self.positional_parameters = parameters;
+    }
+
+    //Whether the exit builtin has been run, after which the shell is meant to end with status
+    pub fn exit_requested(&self) -> bool {
+        self.jump == Some(Jump::Exit)
+    }
+
+    /*
+    Enables job control, for a shell whose stdin is a terminal. The shell gets a process
+    group of its own, which only owns the terminal while no foreground job is running.
+    */
+    pub fn enable_job_control(&mut self) {
+        self.terminal_modes = jobs::take_terminal();
+        self.job_control = true;
+    }
+
+    //Reports the jobs which have finished or been stopped since this was last called
+    pub fn notify_job_changes(&mut self) {
+        self.jobs.update();
+        for line in self.jobs.take_changes() {
+            Output::notice(line);
         }
     }
 
     /*
     Input is parsed by parse_input() from text_processing.
 
-    Executes the command(s). If multiple commands are specified
-    using pipes, they are executed in order and their stdout
-    is always redirected to the subsequent command.
+    Executes the pipeline(s) in order. If multiple commands are
+    chained using pipes, their stdout is always redirected to the
+    subsequent command. Pipelines chained with && or || are only
+    run depending on the exit code of the preceding one.
 
-    If a subcommand (e.g. ${whoami}) is being executed, it returns
+    If a subcommand (e.g. $(whoami)) is being executed, it returns
     Ok(Some(stdout_of_subcommand_as_string)), otherwise Ok(None).
 
     Also manages the exit code.
@@ -46,125 +238,770 @@ impl Session {
         input: &str,
         as_subcommand: bool,
     ) -> Result<Option<String>, Box<dyn Error>> {
+        let list = text_processing::parse_input(input)?;
+        if !as_subcommand {
+            self.execute_list(&list);
+            if self.jump != Some(Jump::Exit) {
+                self.jump = None;
+            }
+            return Ok(None);
+        }
+
+        //Like a subshell, the subcommand runs in a copy of the session with its stdout
+        //going into a pipe, so that e.g. cd or assignments within it don't leak back
+        let (mut reader, writer) = os_pipe::pipe()?;
+        let mut fds = self.fds.try_clone()?;
+        fds.set(1, writer);
+        //Safety: the child only runs the shell's own code and exits afterwards
+        let pid = match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error().into()),
+            0 => {
+                drop(reader);
+                //The subcommand stays in the shell's process group, but Ctrl-C must still stop it
+                if self.job_control {
+                    jobs::restore_signals();
+                }
+                self.job_control = false;
+                let code = self.run_subshell(&CompoundCommand::Subshell(list), &[], fds);
+                process::exit(code);
+            }
+            pid => pid,
+        };
+        //The writing end must be closed here, or reading wouldn't end with the child
+        drop(fds);
+        let mut output = String::new();
+        let result = reader.read_to_string(&mut output);
+        self.status = Self::wait_for(pid);
+        //Only an interruption reaches beyond a subcommand, e.g. break and exit within it don't
+        if self.status == ExitStatus::Signaled(libc::SIGINT) {
+            //The terminal shows ^C without starting a new line
+            if self.job_control {
+                Output::notice("");
+            }
+            self.jump = Some(Jump::Abort);
+        }
+        result?;
+        Ok(Some(output))
+    }
+
+    fn execute_list(&mut self, list: &List) {
+        for and_or in &list.and_or_lists {
+            if self.jump.is_some() {
+                return;
+            }
+            if and_or.background {
+                if let Err(msg) = self.start_job(and_or) {
+                    self.status = ExitStatus::BuiltinError;
+                    Output::shell_error(msg);
+                }
+                continue;
+            }
+
+            let mut pipelines = vec![(None, &and_or.first)];
+            pipelines.extend(and_or.rest.iter().map(|(c, p)| (Some(c), p)));
+            for (connector, pipeline) in pipelines {
+                if self.jump.is_some() {
+                    return;
+                }
+                let succeeded = self.status.success();
+                match connector {
+                    Some(Connector::And) if !succeeded => continue,
+                    Some(Connector::Or) if succeeded => continue,
+                    _ => {}
+                }
+
+                //A failing pipeline doesn't abort the whole input, it merely
+                //leaves a non-zero exit code for the next one to check.
+                if let Err(msg) = self.execute_pipeline(pipeline, false) {
+                    self.status = ExitStatus::BuiltinError;
+                    Output::shell_error(msg);
+                }
+            }
+        }
+    }
+
+    /*
+    Starts an AND-OR list terminated by '&' without waiting for it and adds it to the
+    job table. The processes of a single pipeline become the job, while longer lists
+    run as a whole within a subshell.
+    */
+    fn start_job(&mut self, and_or: &AndOrList) -> Result<(), Box<dyn Error>> {
+        let subshell;
+        let pipeline = if and_or.rest.is_empty() {
+            &and_or.first
+        } else {
+            let mut body = and_or.clone();
+            body.background = false;
+            let list = List {
+                and_or_lists: vec![body],
+            };
+            let command = Command::Compound(CompoundCommand::Subshell(list), Vec::new());
+            subshell = Pipeline {
+                commands: vec![command],
+                negated: false,
+            };
+            &subshell
+        };
+        let pids = self.execute_pipeline(pipeline, true)?;
+
+        let last_pid = pids[pids.len() - 1];
+        let mut command = and_or.clone();
+        command.background = false;
+        let id = self.jobs.add(Job::new(pids, command.to_string()));
+        //Scripts don't announce their jobs
+        if self.job_control {
+            Output::notice(format!("[{}] {}", id, last_pid));
+        }
+        self.last_background_pid = Some(last_pid as u32);
+        self.status = ExitStatus::SUCCESS;
+        Ok(())
+    }
+
+    /*
+    Runs the commands of a pipeline, connecting the stdout of each one
+    to the stdin of the next one, and returns the IDs of the processes
+    started for them.
+
+    In the foreground, the processes are waited for. In the background,
+    every command runs in a child process and nothing is waited for.
+    With job control, and always in the background, the processes form
+    a new process group, led by the first one.
+    */
+    fn execute_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        background: bool,
+    ) -> Result<Vec<i32>, Box<dyn Error>> {
         //Reading end of a pipe, if piping is used. Must be in outer scope because:
         //Writer process creates the pipe. The reading end will be connected to the stdin
         //of the succeeding process, so pipe_reader must survive until the next iteration.
-        let mut pipe_reader = None;
-        
-        let mut instructions = text_processing::parse_input(input)?;
-        let instructions_count = instructions.len();
-        for (instruction_index, instruction) in instructions.iter_mut().enumerate() {
-            let program = instruction.command[0].clone();
-            if program == "ls" {
-                instruction.command.insert(1, "--color=auto".to_string());
-                //Since we have inserted something at index 1, all the subcommand indices are now 1 below what they should be
-                instruction.subcommand_indices.iter_mut().for_each(|index| *index+=1);
-            }
-
-            for &subcommand_i in instruction.subcommand_indices.iter() {
-                //Execute the subcommand and store its stdout
-                let output = self.execute_input(&instruction.command[subcommand_i], true)?;
-                //Substitute the subcommand with its computed stdout.
-                //Unwrap() will not panic, since at this point,
-                //output is Some() because as_subcommand was set to true.
-                instruction.command[subcommand_i] = output.unwrap();
-            }
-
-            if Self::BUILTINS.contains(&program.as_str()) {
-                let mut pipe_writer = None;
-                if let StdoutTo::Pipe = instruction.stdout_to {
-                    let (pr, pw) = os_pipe::pipe()?;
-                    pipe_reader = Some(pr);
-                    pipe_writer = Some(pw);
-                }
-                self.run_builtin(instruction, pipe_writer)?;
-            } else {
-                let mut process_builder = Command::new(&program[..]);
-                process_builder.args(&instruction.command[1..]);
+        let mut pipe_reader: Option<PipeReader> = None;
+        //Builtins write their output from separate threads, so that output larger than
+        //a pipe's buffer doesn't block until the next command reads it.
+        let mut writer_threads = Vec::new();
+        let mut pids = Vec::new();
+        //What each command resulted in, to determine the exit codes once the processes are done
+        let mut stages = Vec::new();
 
-                //If instruction follows a pipe, connect stdin of process to pipe created by
-                //instruction from previous iteration.
-                if instruction.read_from_pipe {
-                    process_builder.stdin(pipe_reader.take().unwrap());
-                }
+        let commands_count = pipeline.commands.len();
+        for (command_index, command) in pipeline.commands.iter().enumerate() {
+            let is_last = command_index == commands_count - 1;
+
+            //Pipes are set up first, so that redirections (e.g. 2>&1) can refer to them
+            let mut fds = self.fds.try_clone()?;
+            if let Some(reader) = pipe_reader.take() {
+                fds.set(0, reader);
+            }
+            if !is_last {
+                let (reader, writer) = os_pipe::pipe()?;
+                fds.set(1, writer);
+                //pipe_reader needs to be accessed by succeeding command in pipe chain.
+                pipe_reader = Some(reader);
+            }
 
-                //Subcommands also write to a pipe 
-                let mut set_up_pipe = as_subcommand;
-                match instruction.stdout_to {
-                    StdoutTo::Stdout => {}
-                    StdoutTo::Pipe => {
-                        set_up_pipe = true;
+            //The first process leads the group (0 = itself)
+            let process_group =
+                (background || self.job_control).then(|| pids.first().copied().unwrap_or(0));
+            match command {
+                //Commands of a longer pipeline run in a child process, so that builtins and
+                //functions neither change the session (e.g. exit or cd) nor block on a full
+                //pipe before the next command has been started
+                Command::Simple(command) if !background && commands_count == 1 => {
+                    let result = self.run_simple_command(
+                        command,
+                        fds,
+                        process_group,
+                        &mut writer_threads,
+                        false,
+                    );
+                    //A command failing (e.g. to open a file) doesn't keep the others from running
+                    let outcome = result.unwrap_or_else(|msg| {
+                        Output::shell_error(msg);
+                        Outcome::Finished(ExitStatus::BuiltinError)
+                    });
+                    if let Outcome::Spawned(pid) = outcome {
+                        pids.push(pid);
                     }
-                    StdoutTo::File(mode) => {
-                        if instruction.filename.is_empty() {
-                            return Err(Box::from(format!("Please specify output file for '{}'", program)));
+                    stages.push(outcome);
+                }
+                //A compound command on its own runs in the current session (unless it's a
+                //subshell), everything else in a child process
+                Command::Compound(compound, redirections)
+                    if !background
+                        && commands_count == 1
+                        && !matches!(compound, CompoundCommand::Subshell(_)) =>
+                {
+                    self.apply_redirections(redirections, &mut fds)?;
+                    let outer_fds = mem::replace(&mut self.fds, fds);
+                    let result = self.execute_compound(compound);
+                    self.fds = outer_fds;
+                    result?;
+                    stages.push(Outcome::Finished(self.status));
+                }
+                Command::Function(function) if !background && commands_count == 1 => {
+                    self.functions
+                        .insert(function.name.clone(), Rc::clone(function));
+                    stages.push(Outcome::Finished(ExitStatus::SUCCESS));
+                }
+                _ => {
+                    //Safety: the child only runs the shell's own code and exits afterwards
+                    match unsafe { libc::fork() } {
+                        -1 => return Err(io::Error::last_os_error().into()),
+                        0 => {
+                            //The reading end of the child's own output must be closed,
+                            //or writing to it wouldn't fail once the next command is gone
+                            drop(pipe_reader.take());
+                            if let Some(pgid) = process_group {
+                                jobs::enter_job(pgid, !background);
+                            }
+                            let code = self.run_forked(command, fds);
+                            process::exit(code);
+                        }
+                        pid => {
+                            //Set by both processes, so that the group exists whichever runs first
+                            if let Some(pgid) = process_group {
+                                let pgid = if pgid == 0 { pid } else { pgid };
+                                //Safety: setpgid only changes the process group of the child
+                                unsafe { libc::setpgid(pid, pgid) };
+                            }
+                            pids.push(pid);
+                            stages.push(Outcome::Spawned(pid));
                         }
-
-                        let file = utils::open_file(&instruction.filename, mode)?;
-                        process_builder.stdout(file);
                     }
                 }
-                
-                if set_up_pipe {
-                    if let Ok((reader, writer)) = os_pipe::pipe() {
-                        //pipe_reader needs to be accessed by succeeding instruction in pipe chain.
-                        pipe_reader = Some(reader);
-                        //PipeWriter only needed here.
-                        process_builder.stdout(writer);
+            }
+        }
+
+        if !background {
+            let spawned = if pids.is_empty() {
+                Vec::new()
+            } else {
+                let job = Job::new(pids.clone(), pipeline.to_string());
+                self.wait_in_foreground(job, false)
+            };
+            let mut spawned = spawned.into_iter();
+            let statuses: Vec<ExitStatus> = stages
+                .into_iter()
+                .map(|stage| match stage {
+                    Outcome::Finished(status) => status,
+                    Outcome::Spawned(_) => spawned.next().unwrap_or(ExitStatus::SUCCESS),
+                })
+                .collect();
+            //With pipefail, a pipeline fails if any of its commands does, with the status of the last one failing
+            let last_failure = statuses.iter().rev().find(|status| !status.success());
+            self.status = match last_failure {
+                Some(status) if self.option_enabled("pipefail") => *status,
+                _ => statuses.last().copied().unwrap_or(ExitStatus::SUCCESS),
+            };
+            if pipeline.negated {
+                self.status = match self.status.success() {
+                    true => ExitStatus::Code(1),
+                    false => ExitStatus::SUCCESS,
+                };
+            }
+            self.pipe_status = statuses;
+        }
+        for writer_thread in writer_threads {
+            let _ = writer_thread.join();
+        }
+        Ok(pids)
+    }
+
+    /*
+    Waits for a job in the foreground, after letting it continue if resume is set, and
+    returns the exit statuses of its processes. With job control, the job gets the terminal
+    meanwhile, and if it's stopped (e.g. by Ctrl-Z), it's added to the job table.
+    */
+    fn wait_in_foreground(&mut self, mut job: Job, resume: bool) -> Vec<ExitStatus> {
+        if self.job_control {
+            if let Some(modes) = &job.modes {
+                jobs::set_terminal_modes(modes);
+            }
+            jobs::set_foreground(job.pgid);
+        }
+        if resume {
+            if let Err(msg) = job.resume() {
+                Output::shell_error(msg);
+            }
+        }
+        job.reap(true);
+        let statuses = job.statuses();
+        if self.job_control {
+            job.modes = jobs::terminal_modes();
+            jobs::reclaim_terminal(self.terminal_modes.as_ref());
+            //The terminal shows ^C without starting a new line
+            if statuses.contains(&ExitStatus::Signaled(libc::SIGINT)) {
+                Output::notice("");
+            }
+            //Like the shell itself would have been, the rest of the input is interrupted too
+            if statuses.contains(&ExitStatus::Signaled(libc::SIGINT))
+                || job.state == JobState::Stopped
+            {
+                self.jump = Some(Jump::Abort);
+            }
+        }
+        //Processes killed by a signal are reported, except for the usual ways of stopping them early
+        let killed = statuses.iter().rev().find(|status| {
+            let ExitStatus::Signaled(signal) = status else {
+                return false;
+            };
+            ![libc::SIGINT, libc::SIGPIPE].contains(signal)
+        });
+        if let Some(status) = killed {
+            let name = status.signal_name().unwrap_or_default();
+            Output::notice(format!("{} ({})", status.describe(), name));
+        }
+
+        if job.state == JobState::Stopped {
+            let id = self.jobs.add(job);
+            //A new line, since the terminal shows ^Z without one
+            Output::notice(format!(
+                "\n{}",
+                self.jobs.status_line(id).unwrap_or_default()
+            ));
+        }
+        statuses
+    }
+
+    /*
+    Expands and runs a simple command with the given descriptors. Builtins and assignments
+    run within the session, programs are spawned without being waited for, moving them into
+    process_group if it's set. With exec set, programs replace the shell's process instead,
+    which is meant for forked copies of it.
+    */
+    fn run_simple_command(
+        &mut self,
+        command: &SimpleCommand,
+        mut fds: FdTable,
+        process_group: Option<i32>,
+        writer_threads: &mut Vec<thread::JoinHandle<io::Result<()>>>,
+        exec: bool,
+    ) -> Result<Outcome, Box<dyn Error>> {
+        let mut assignments = Vec::new();
+        for assignment in &command.assignments {
+            let value = text_processing::expand_word_to_string(&assignment.value, self)?;
+            assignments.push((assignment.name.clone(), value));
+        }
+        let mut args = Vec::new();
+        for word in &command.words {
+            args.extend(text_processing::expand_word(word, self)?);
+        }
+        self.apply_redirections(&command.redirections, &mut fds)?;
+        //A subcommand interrupted by Ctrl-C interrupts the command it's part of as well
+        if self.jump == Some(Jump::Abort) {
+            return Ok(Outcome::Finished(self.status));
+        }
+
+        if args.is_empty() {
+            //Without a command, assignments apply to the session itself
+            for (name, value) in assignments {
+                self.set_var(&name, value);
+            }
+            return Ok(Outcome::Finished(ExitStatus::SUCCESS));
+        }
+        let program = args[0].clone();
+        if program == "ls" {
+            args.insert(1, "--color=auto".to_string());
+        }
+
+        //Functions take precedence over builtins, which take precedence over programs
+        let function = self.functions.get(&program).cloned();
+        if function.is_some() || Self::BUILTINS.contains(&program.as_str()) {
+            //Assignments in front of a function or builtin only last as long as it runs
+            let previous_values: Vec<_> = assignments
+                .into_iter()
+                .map(|(name, value)| {
+                    let previous = self.vars.get(&name).cloned();
+                    self.set_var(&name, value);
+                    (name, previous)
+                })
+                .collect();
+            let result = match function {
+                Some(function) => self.call_function(&function, &args, &fds),
+                None => self.run_builtin(&args).and_then(|output| {
+                    if let (false, Some(mut writer)) = (output.is_empty(), fds.writer(1)?) {
+                        io::stdout().flush()?;
+                        writer_threads
+                            .push(thread::spawn(move || writer.write_all(output.as_bytes())));
+                    }
+                    Ok(ExitStatus::SUCCESS)
+                }),
+            };
+            for (name, previous) in previous_values {
+                match previous {
+                    Some(variable) => self.vars.insert(name, variable),
+                    None => self.vars.remove(&name),
+                };
+            }
+
+            match result {
+                Ok(status) => Ok(Outcome::Finished(status)),
+                Err(msg) => match msg.downcast_ref::<Failure>() {
+                    Some(Failure(status)) => Ok(Outcome::Finished(*status)),
+                    None => {
+                        Self::report_error(&fds, msg)?;
+                        Ok(Outcome::Finished(ExitStatus::BuiltinError))
                     }
+                },
+            }
+        } else {
+            let mut process_builder = process::Command::new(&program);
+            process_builder.args(&args[1..]);
+            //Only exported variables (and the ones assigned for this command) are passed on
+            process_builder.env_clear();
+            process_builder.envs(
+                self.vars
+                    .iter()
+                    .filter(|(_, variable)| variable.exported)
+                    .map(|(name, variable)| (name, &variable.value)),
+            );
+            process_builder.envs(assignments);
+            if let Some(pgid) = process_group {
+                //Before the descriptors are applied, so that stdin still is the terminal
+                let foreground = self.job_control;
+                //Safety: enter_job is async-signal-safe
+                unsafe {
+                    process_builder.pre_exec(move || {
+                        jobs::enter_job(pgid, foreground);
+                        Ok(())
+                    })
+                };
+            }
+            fds.apply(&mut process_builder);
+
+            let spawned = if exec {
+                //Only returns if the program couldn't be executed
+                Err(process_builder.exec())
+            } else {
+                process_builder.spawn()
+            };
+            match spawned {
+                Ok(child) => Ok(Outcome::Spawned(child.id() as i32)),
+                //Like other shells, 127 if the program doesn't exist and 126 if it can't be run
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    let msg = format!("Command '{}' not found.", program);
+                    Self::report_error(&fds, Box::from(msg))?;
+                    Ok(Outcome::Finished(ExitStatus::NotFound))
                 }
-                
-                let mut current_process: Child;
-                if let Ok(child) = process_builder.spawn() {
-                    current_process = child;
-                } else {
-                    self.exit_code = "?".to_string();
-                    return Err(Box::from(format!("Command '{}' not found.", program)));
+                Err(err) => {
+                    let msg = format!("{}: {}", program, err);
+                    Self::report_error(&fds, Box::from(msg))?;
+                    Ok(Outcome::Finished(ExitStatus::NotExecutable))
                 }
+            }
+        }
+    }
+
+    /*
+    Calls a function with the arguments following its name as positional parameters, its
+    commands starting out with the descriptors of fds. Returns the exit status of the body,
+    or the one given to return.
+    */
+    fn call_function(
+        &mut self,
+        function: &FunctionDefinition,
+        args: &[String],
+        fds: &FdTable,
+    ) -> Result<ExitStatus, Box<dyn Error>> {
+        if self.local_scopes.len() == Self::MAX_FUNCTION_DEPTH {
+            let msg = format!(
+                "{}: maximum function nesting level exceeded ({})",
+                function.name,
+                Self::MAX_FUNCTION_DEPTH
+            );
+            return Err(msg.into());
+        }
+        let mut fds = fds.try_clone()?;
+        self.apply_redirections(&function.redirections, &mut fds)?;
+        let outer_fds = mem::replace(&mut self.fds, fds);
+        let outer_parameters = mem::replace(&mut self.positional_parameters, args[1..].to_vec());
+        //The caller's loops can't be left with break or continue from within the function
+        let outer_loop_depth = mem::take(&mut self.loop_depth);
+        self.local_scopes.push(Vec::new());
 
+        let result = self.execute_compound(&function.body);
 
-                if instruction_index == instructions_count - 1 {
-                    //Wait for last process to finish and collect exit status
-                    if let Ok(exit_status) = current_process.wait() {
-                        if let Some(code) = exit_status.code() {
-                            self.exit_code = code.to_string();
-                        } else {
-                            //No exit code (process was terminated by a signal)
-                            self.exit_code = "!".to_string();
+        for (name, previous) in self.local_scopes.pop().unwrap_or_default() {
+            match previous {
+                Some(variable) => self.vars.insert(name, variable),
+                None => self.vars.remove(&name),
+            };
+        }
+        self.loop_depth = outer_loop_depth;
+        self.positional_parameters = outer_parameters;
+        self.fds = outer_fds;
+        result?;
+        if let Some(Jump::Return(status)) = self.jump {
+            self.jump = None;
+            self.status = status;
+        }
+        Ok(self.status)
+    }
+
+    //Waits for a child process to finish and returns its exit status
+    fn wait_for(pid: i32) -> ExitStatus {
+        let mut status = 0;
+        loop {
+            //Safety: status points to a valid integer
+            if unsafe { libc::waitpid(pid, &mut status, 0) } >= 0 {
+                break;
+            }
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                return ExitStatus::NotFound;
+            }
+        }
+        ExitStatus::from_wait_status(status)
+    }
+
+    /*
+    Runs a command within a forked copy of the shell and returns the exit code the
+    child process should exit with. Programs take the place of the copy.
+    */
+    fn run_forked(&mut self, command: &Command, fds: FdTable) -> i32 {
+        //Only the shell itself controls jobs, the copy's pipelines are part of its own job
+        self.job_control = false;
+        let command = match command {
+            Command::Simple(command) => command,
+            Command::Compound(compound, redirections) => {
+                return self.run_subshell(compound, redirections, fds)
+            }
+            //A function defined within a child process is gone along with it
+            Command::Function(_) => return 0,
+        };
+        let mut writer_threads = Vec::new();
+        let status = match self.run_simple_command(command, fds, None, &mut writer_threads, true) {
+            Ok(Outcome::Finished(status)) => status,
+            Ok(Outcome::Spawned(pid)) => Self::wait_for(pid),
+            Err(msg) => {
+                Output::shell_error(msg);
+                ExitStatus::BuiltinError
+            }
+        };
+        for writer_thread in writer_threads {
+            let _ = writer_thread.join();
+        }
+        let _ = io::stdout().flush();
+        status.code()
+    }
+
+    /*
+    Runs a compound command within a forked copy of the shell and returns the exit code
+    the child process should exit with. Changes to the session (variables, the working
+    directory etc.) only affect the copy.
+    */
+    fn run_subshell(
+        &mut self,
+        compound: &CompoundCommand,
+        redirections: &[Redirection],
+        mut fds: FdTable,
+    ) -> i32 {
+        if let Err(msg) = self.apply_redirections(redirections, &mut fds) {
+            Output::shell_error(msg);
+            return 1;
+        }
+        //The descriptors become the subshell's own ones, which every command within inherits
+        if let Err(msg) = fds.install() {
+            Output::shell_error(msg.into());
+            return 1;
+        }
+        self.fds = FdTable::new();
+        if let Err(msg) = self.execute_compound(compound) {
+            Output::shell_error(msg);
+            self.status = ExitStatus::BuiltinError;
+        }
+        let _ = io::stdout().flush();
+        self.status.code()
+    }
+
+    /*
+    Runs a compound command within the current session, leaving its exit status in status.
+    Subshells are expected to have been forked already, so their body is run directly.
+    */
+    fn execute_compound(&mut self, compound: &CompoundCommand) -> Result<(), Box<dyn Error>> {
+        match compound {
+            CompoundCommand::Subshell(body) | CompoundCommand::Group(body) => {
+                self.execute_list(body)
+            }
+            CompoundCommand::If {
+                branches,
+                else_body,
+            } => {
+                for (condition, body) in branches {
+                    self.execute_list(condition);
+                    if self.jump.is_some() {
+                        return Ok(());
+                    }
+                    if self.status.success() {
+                        self.execute_list(body);
+                        return Ok(());
+                    }
+                }
+                //Without a branch being taken, the if succeeds
+                match else_body {
+                    Some(body) => self.execute_list(body),
+                    None => self.status = ExitStatus::SUCCESS,
+                }
+            }
+            CompoundCommand::While {
+                condition,
+                body,
+                until,
+            } => {
+                //A loop whose body never runs succeeds
+                let mut status = ExitStatus::SUCCESS;
+                self.loop_depth += 1;
+                loop {
+                    self.execute_list(condition);
+                    if self.jump.is_none() {
+                        if self.status.success() == *until {
+                            break;
+                        }
+                        self.execute_list(body);
+                        status = self.status;
+                    }
+                    if self.leave_loop() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+                self.status = status;
+            }
+            CompoundCommand::For {
+                variable,
+                words,
+                body,
+            } => {
+                let values = match words {
+                    Some(words) => {
+                        let mut values = Vec::new();
+                        for word in words {
+                            values.extend(text_processing::expand_word(word, self)?);
+                        }
+                        values
+                    }
+                    None => self.positional_parameters.clone(),
+                };
+                self.status = ExitStatus::SUCCESS;
+                self.loop_depth += 1;
+                for value in values {
+                    self.set_var(variable, value);
+                    self.execute_list(body);
+                    if self.leave_loop() {
+                        break;
+                    }
+                }
+                self.loop_depth -= 1;
+            }
+            CompoundCommand::Case { word, items } => {
+                let value = text_processing::expand_word_to_string(word, self)?;
+                self.status = ExitStatus::SUCCESS;
+                //Only the body of the first item with a matching pattern is run
+                for item in items {
+                    for pattern in &item.patterns {
+                        let pattern = text_processing::expand_word_to_pattern(pattern, self)?;
+                        if glob::matches(&pattern, &value) {
+                            self.execute_list(&item.body);
+                            return Ok(());
                         }
-                    } else {
-                        self.exit_code = "?".to_string();
                     }
                 }
             }
         }
+        Ok(())
+    }
 
-        if as_subcommand {
-            let mut command_output = String::new();
-            //The last instruction within a subcommand writes to a pipe, but no process reads it.
-            //Instead, it's read to a String.
-            if let Some(mut pr) = pipe_reader {
-                pr.read_to_string(&mut command_output)?;
+    /*
+    Called at the end of an iteration of a loop, returns whether the loop is to be left
+    due to break, continue or an abort. Jumps beyond the loop are passed on to the
+    enclosing ones.
+    */
+    fn leave_loop(&mut self) -> bool {
+        match self.jump.take() {
+            None | Some(Jump::Continue(1)) => false,
+            Some(Jump::Break(1)) => true,
+            Some(Jump::Break(levels)) => {
+                self.jump = Some(Jump::Break(levels - 1));
+                true
+            }
+            Some(Jump::Continue(levels)) => {
+                self.jump = Some(Jump::Continue(levels - 1));
+                true
+            }
+            Some(jump @ (Jump::Return(_) | Jump::Abort | Jump::Exit)) => {
+                self.jump = Some(jump);
+                true
             }
-            return Ok(Some(String::from(command_output.trim())));
         }
-
-        Ok(None)
     }
 
     /*
-    Determines which builtin command has been issued
-    and runs the appropriate logic
+    Opens the files of the redirections in order and points
+    the file descriptors of fds to them.
     */
-    fn run_builtin(
+    fn apply_redirections(
         &mut self,
-        instruction: &Instruction,
-        pipe_writer: Option<PipeWriter>, //Some(), if this instruction precedes a pipe.
+        redirections: &[Redirection],
+        fds: &mut FdTable,
     ) -> Result<(), Box<dyn Error>> {
+        for redirection in redirections {
+            let fd = redirection.fd;
+            let target = text_processing::expand_word_to_string(&redirection.target, self)?;
+            match redirection.kind {
+                RedirectionKind::Overwrite => fds.set(fd, utils::open_file(&target, 'o')?),
+                RedirectionKind::Append => fds.set(fd, utils::open_file(&target, 'a')?),
+                RedirectionKind::Input => {
+                    let file = File::open(&target)
+                        .map_err(|e| format!("Cannot read '{}': {}", target, e))?;
+                    fds.set(fd, file);
+                }
+                RedirectionKind::HereString => {
+                    fds.set(fd, utils::pipe_with_contents(format!("{}\n", target))?);
+                }
+                RedirectionKind::HereDoc => {
+                    fds.set(fd, utils::pipe_with_contents(target)?);
+                }
+                RedirectionKind::Duplicate => {
+                    if target == "-" {
+                        fds.close(fd);
+                    } else if let Ok(source) = target.parse::<u32>() {
+                        fds.duplicate(fd, source)
+                            .map_err(|e| format!("{}: {}", source, e))?;
+                    } else if fd == 1 {
+                        //>&file is the same as &>file
+                        fds.set(1, utils::open_file(&target, 'o')?);
+                        fds.duplicate(2, 1)?;
+                    } else {
+                        return Err(format!("'{}': ambiguous redirect", target).into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    Writes the error of a command to its stderr. If stderr hasn't
+    been redirected, it's displayed as a shell error.
+    */
+    fn report_error(fds: &FdTable, msg: Box<dyn Error>) -> Result<(), Box<dyn Error>> {
+        if !fds.is_redirected(2) {
+            Output::shell_error(msg);
+        } else if let Some(mut writer) = fds.writer(2)? {
+            writeln!(writer, "{}", msg)?;
+        }
+        Ok(())
+    }
+
+    /*
+    Determines which builtin command has been issued
+    and runs the appropriate logic. Returns what the
+    builtin writes to stdout.
+    */
+    fn run_builtin(&mut self, args: &[String]) -> Result<String, Box<dyn Error>> {
         let mut output = String::new();
-        match instruction.command[0].as_str() {
+        match args[0].as_str() {
             "cd" => {
-                if let Some(target_path) = instruction.command.get(1) {
+                if let Some(target_path) = args.get(1) {
                     env::set_current_dir(Path::new(target_path))?;
                 } else {
                     env::set_current_dir(utils::home_dir()?)?;
@@ -172,10 +1009,10 @@ impl Session {
                 self.cwd = env::current_dir()?;
             }
             "pwd" => {
-                output = format!("{}", self.cwd.display().to_string());
+                output = self.cwd.display().to_string();
             }
             "pushd" => {
-                if let Some(target_path) = instruction.command.get(1) {
+                if let Some(target_path) = args.get(1) {
                     //If dir stack is empty, the current working directory
                     //becomes its first element
                     if self.dir_stack.is_empty() {
@@ -186,14 +1023,12 @@ impl Session {
                     self.cwd = env::current_dir()?;
                     self.dir_stack.push(self.cwd.clone());
                 } else {
-                    self.exit_code = "!".to_string();
                     let msg = "Please specify a directory".to_string();
                     return Err(Box::from(msg));
                 }
             }
             "popd" => {
-                if self.dir_stack.len() == 0 {
-                    self.exit_code = "!".to_string();
+                if self.dir_stack.is_empty() {
                     let msg = "Directory stack empty.".to_string();
                     return Err(Box::from(msg));
                 }
@@ -212,33 +1047,265 @@ impl Session {
                     }
                 }
             }
+            "export" => {
+                if args.len() == 1 {
+                    let mut exported: Vec<_> =
+                        self.vars.iter().filter(|(_, v)| v.exported).collect();
+                    exported.sort_by_key(|(name, _)| *name);
+                    for (name, variable) in exported {
+                        output.push_str(&format!("{}={}\n", name, variable.value));
+                    }
+                    output.pop();
+                }
+                for arg in &args[1..] {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name, Some(value.to_string())),
+                        None => (arg.as_str(), None),
+                    };
+                    if !lexer::is_variable_name(name) {
+                        return Err(format!("'{}' is not a valid variable name", name).into());
+                    }
+                    let value = value.or_else(|| self.get_var(name)).unwrap_or_default();
+                    let exported = true;
+                    self.vars
+                        .insert(name.to_string(), Variable { value, exported });
+                }
+            }
+            "unset" => {
+                //With -f, the names are the ones of functions rather than variables
+                match args.get(1).map(String::as_str) {
+                    Some("-f") => {
+                        for name in &args[2..] {
+                            self.functions.remove(name);
+                        }
+                    }
+                    _ => {
+                        for name in &args[1..] {
+                            self.vars.remove(name);
+                        }
+                    }
+                }
+            }
+            "set" => {
+                //Without arguments, the state of every option is listed
+                if args.len() == 1 {
+                    for option in Self::OPTIONS {
+                        let state = if self.option_enabled(option) {
+                            "on"
+                        } else {
+                            "off"
+                        };
+                        output.push_str(&format!("{:<24}{}\n", option, state));
+                    }
+                    output.pop();
+                }
+                let mut args_iter = args[1..].iter();
+                while let Some(flag) = args_iter.next() {
+                    let enable = match flag.as_str() {
+                        "-o" => true,
+                        "+o" => false,
+                        _ => return Err(format!("set: unknown argument '{}'", flag).into()),
+                    };
+                    let name = args_iter.next().ok_or("set: option name expected")?;
+                    let option = Self::OPTIONS
+                        .into_iter()
+                        .find(|option| option == name)
+                        .ok_or(format!("set: unknown option '{}'", name))?;
+                    if enable {
+                        self.options.insert(option);
+                    } else {
+                        self.options.remove(option);
+                    }
+                }
+            }
+            "let" => {
+                if args.len() == 1 {
+                    return Err("let: expression expected".into());
+                }
+                let mut value = 0;
+                for expression in &args[1..] {
+                    value = arithmetic::evaluate(expression, self)?;
+                }
+                //Like ((...)), let fails if the last expression evaluates to 0
+                if value == 0 {
+                    return Err(Box::new(Failure(ExitStatus::Code(1))));
+                }
+            }
+            "jobs" => {
+                self.jobs.update();
+                let lines: Vec<String> = match args.get(1).map(String::as_str) {
+                    None => self.jobs.status_lines(),
+                    Some("-p") => self
+                        .jobs
+                        .process_groups()
+                        .iter()
+                        .map(|pgid| pgid.to_string())
+                        .collect(),
+                    Some(arg) => return Err(format!("jobs: unknown argument '{}'", arg).into()),
+                };
+                output = lines.join("\n");
+                //Finished jobs are listed once, like in the notices before the prompt
+                self.jobs.take_changes();
+            }
+            "fg" => {
+                let id = self.jobs.find(args.get(1).map(String::as_str))?;
+                let Some(job) = self.jobs.remove(id) else {
+                    return Err(format!("fg: job {} has finished", id).into());
+                };
+                Output::notice(&job.command);
+                let statuses = self.wait_in_foreground(job, true);
+                let status = statuses.last().copied().unwrap_or(ExitStatus::SUCCESS);
+                if !status.success() {
+                    return Err(Box::new(Failure(status)));
+                }
+            }
+            "bg" => {
+                let id = self.jobs.find(args.get(1).map(String::as_str))?;
+                if let Some(job) = self.jobs.get_mut(id) {
+                    job.resume()?;
+                    output = format!("[{}] {} &", id, job.command);
+                }
+                self.jobs.touch(id);
+            }
+            "disown" => {
+                let id = self.jobs.find(args.get(1).map(String::as_str))?;
+                self.jobs.disown(id);
+            }
+            "wait" => {
+                //Without arguments, every running job is waited for
+                let ids = if args.len() == 1 {
+                    self.jobs.running()
+                } else {
+                    let mut ids = Vec::new();
+                    for arg in &args[1..] {
+                        let id = match arg.parse::<i32>() {
+                            Ok(pid) => self.jobs.find_pid(pid).ok_or_else(|| {
+                                format!("wait: process {} isn't a child of this shell", pid)
+                            })?,
+                            Err(_) => self.jobs.find(Some(arg))?,
+                        };
+                        ids.push(id);
+                    }
+                    ids
+                };
+                let mut status = ExitStatus::SUCCESS;
+                for id in ids {
+                    if let Some(job) = self.jobs.get_mut(id) {
+                        //Ctrl-C stops waiting, though not the job, which doesn't run in the foreground
+                        if !self.job_control {
+                            job.reap(true);
+                        } else if !job.reap_interruptibly() {
+                            //The terminal shows ^C without starting a new line
+                            Output::notice("");
+                            self.jump = Some(Jump::Abort);
+                            return Err(Box::new(Failure(ExitStatus::Code(130))));
+                        }
+                        status = match &job.state {
+                            JobState::Done(status) => *status,
+                            _ => ExitStatus::Stopped(libc::SIGTSTP),
+                        };
+                    }
+                }
+                if !status.success() {
+                    return Err(Box::new(Failure(status)));
+                }
+            }
+            "break" | "continue" => {
+                if self.loop_depth == 0 {
+                    return Err(format!("{}: only meaningful in a loop", args[0]).into());
+                }
+                //The number of loops to leave, all of them if it exceeds the number running
+                let levels = match args.get(1).map(|arg| arg.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(levels)) if levels > 0 => levels.min(self.loop_depth),
+                    Some(_) => {
+                        let msg = format!("{}: {}: loop count out of range", args[0], args[1]);
+                        return Err(msg.into());
+                    }
+                };
+                self.jump = Some(if args[0] == "break" {
+                    Jump::Break(levels)
+                } else {
+                    Jump::Continue(levels)
+                });
+            }
+            "local" => {
+                if self.local_scopes.is_empty() {
+                    return Err("local: can only be used in a function".into());
+                }
+                for arg in &args[1..] {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name, Some(value.to_string())),
+                        None => (arg.as_str(), None),
+                    };
+                    if !lexer::is_variable_name(name) {
+                        return Err(format!("local: '{}' isn't a valid variable name", name).into());
+                    }
+                    //The value from before the call is kept to be restored once the function returns
+                    let previous = self.vars.get(name).cloned();
+                    if let Some(scope) = self.local_scopes.last_mut() {
+                        if scope.iter().all(|(local, _)| local != name) {
+                            scope.push((name.to_string(), previous));
+                        }
+                    }
+                    match value {
+                        Some(value) => self.set_var(name, value),
+                        None => {
+                            self.vars.remove(name);
+                        }
+                    }
+                }
+            }
+            "return" => {
+                if self.local_scopes.is_empty() {
+                    return Err("return: can only be used in a function".into());
+                }
+                //Without a number, the function returns the status of the last command
+                let status = match args.get(1) {
+                    None => self.status,
+                    Some(arg) => match arg.parse::<i32>() {
+                        Ok(code) => ExitStatus::Code(code.rem_euclid(256)),
+                        Err(_) => {
+                            let msg = format!("return: {}: numeric argument required", arg);
+                            return Err(msg.into());
+                        }
+                    },
+                };
+                self.jump = Some(Jump::Return(status));
+            }
+            "exit" => {
+                //Without a number, the shell exits with the status of the last command
+                let status = match args.get(1) {
+                    None => self.status,
+                    Some(arg) => match arg.parse::<i32>() {
+                        Ok(code) => ExitStatus::Code(code.rem_euclid(256)),
+                        Err(_) => {
+                            let msg = format!("exit: {}: numeric argument required", arg);
+                            return Err(msg.into());
+                        }
+                    },
+                };
+                self.jump = Some(Jump::Exit);
+                //Passed on like the status of a failing builtin, so that it becomes the shell's status
+                return Err(Box::new(Failure(status)));
+            }
+            //: and true do nothing but succeed, e.g. for endless loops
+            ":" | "true" => {}
+            "false" => return Err(Box::new(Failure(ExitStatus::Code(1)))),
             "history" => {
-                let history_result = utils::read_history()?;
-                let history = String::from_utf8(history_result)?;
-                for (i, line) in history.lines().enumerate() {
+                for (i, entry) in utils::read_history()?.iter().enumerate() {
                     if i != 0 {
-                        output.push_str("\n");
+                        output.push('\n');
                     }
-                    output.push_str(&format!("{} {}", i, line));
+                    output.push_str(&format!("{} {}", i, entry));
                 }
             }
             _ => {}
         }
 
         if !output.is_empty() {
-            output.push_str("\r\n");
-            if let StdoutTo::File(mode) = instruction.stdout_to {
-                let mut file = utils::open_file(&instruction.filename, mode)?;
-                file.write(output.as_bytes())?;
-            } else {
-                if let Some(mut pw) = pipe_writer {
-                    pw.write(output.as_bytes())?;
-                } else {
-                    print!("{}", output);
-                    return Ok(());
-                }
-            }
+            output.push('\n');
         }
-        Ok(())
+        Ok(output)
     }
-}
\ No newline at end of file
+}
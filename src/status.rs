@@ -0,0 +1,163 @@
+use std::fmt;
+
+//How a command finished. Displays as the number $? expands to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitStatus {
+    Code(i32),     //Exit code of a program, or of a builtin succeeding or failing silently
+    Signaled(i32), //Terminated by the signal with this number, 128 + the number
+    Stopped(i32),  //Stopped by the signal with this number (e.g. Ctrl-Z), 128 + the number
+    NotFound,      //The program doesn't exist, 127
+    NotExecutable, //The program exists, but couldn't be executed (e.g. no permission), 126
+    BuiltinError,  //A builtin (or the shell itself) reported an error, 1
+}
+
+//Names of the common signals and how they're described when they terminate a process
+const SIGNALS: [(i32, &str, &str); 19] = [
+    (libc::SIGHUP, "SIGHUP", "Hangup"),
+    (libc::SIGINT, "SIGINT", "Interrupt"),
+    (libc::SIGQUIT, "SIGQUIT", "Quit"),
+    (libc::SIGILL, "SIGILL", "Illegal instruction"),
+    (libc::SIGTRAP, "SIGTRAP", "Trace/breakpoint trap"),
+    (libc::SIGABRT, "SIGABRT", "Aborted"),
+    (libc::SIGBUS, "SIGBUS", "Bus error"),
+    (libc::SIGFPE, "SIGFPE", "Floating point exception"),
+    (libc::SIGKILL, "SIGKILL", "Killed"),
+    (libc::SIGUSR1, "SIGUSR1", "User defined signal 1"),
+    (libc::SIGSEGV, "SIGSEGV", "Segmentation fault"),
+    (libc::SIGUSR2, "SIGUSR2", "User defined signal 2"),
+    (libc::SIGPIPE, "SIGPIPE", "Broken pipe"),
+    (libc::SIGALRM, "SIGALRM", "Alarm clock"),
+    (libc::SIGTERM, "SIGTERM", "Terminated"),
+    (libc::SIGSTOP, "SIGSTOP", "Stopped (signal)"),
+    (libc::SIGTSTP, "SIGTSTP", "Stopped"),
+    (libc::SIGTTIN, "SIGTTIN", "Stopped (tty input)"),
+    (libc::SIGTTOU, "SIGTTOU", "Stopped (tty output)"),
+];
+
+impl ExitStatus {
+    pub const SUCCESS: ExitStatus = ExitStatus::Code(0);
+
+    //Decodes the status reported by waitpid()
+    pub fn from_wait_status(status: i32) -> Self {
+        if libc::WIFEXITED(status) {
+            ExitStatus::Code(libc::WEXITSTATUS(status))
+        } else if libc::WIFSTOPPED(status) {
+            ExitStatus::Stopped(libc::WSTOPSIG(status))
+        } else {
+            ExitStatus::Signaled(libc::WTERMSIG(status))
+        }
+    }
+
+    //The number $? expands to
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Code(code) => *code,
+            ExitStatus::Signaled(signal) | ExitStatus::Stopped(signal) => 128 + signal,
+            ExitStatus::NotFound => 127,
+            ExitStatus::NotExecutable => 126,
+            ExitStatus::BuiltinError => 1,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.code() == 0
+    }
+
+    //Name of the signal which terminated or stopped the process, e.g. SIGKILL
+    pub fn signal_name(&self) -> Option<String> {
+        let (ExitStatus::Signaled(signal) | ExitStatus::Stopped(signal)) = self else {
+            return None;
+        };
+        Some(
+            match SIGNALS.iter().find(|(number, _, _)| number == signal) {
+                Some((_, name, _)) => name.to_string(),
+                None => format!("SIG{}", signal),
+            },
+        )
+    }
+
+    /*
+    Describes the status for job notices, e.g. "Done", "Exit 2" or "Killed".
+    Signals without a description are described by their name.
+    */
+    pub fn describe(&self) -> String {
+        let (ExitStatus::Signaled(signal) | ExitStatus::Stopped(signal)) = self else {
+            return match self.code() {
+                0 => "Done".to_string(),
+                code => format!("Exit {}", code),
+            };
+        };
+        match SIGNALS.iter().find(|(number, _, _)| number == signal) {
+            Some((_, _, description)) => description.to_string(),
+            None => format!("Signal {}", signal),
+        }
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_statuses_to_codes() {
+        assert_eq!(ExitStatus::SUCCESS.code(), 0);
+        assert_eq!(ExitStatus::Code(3).code(), 3);
+        assert_eq!(ExitStatus::Signaled(libc::SIGINT).code(), 130);
+        assert_eq!(ExitStatus::Signaled(libc::SIGKILL).code(), 137);
+        assert_eq!(
+            ExitStatus::Stopped(libc::SIGTSTP).code(),
+            128 + libc::SIGTSTP
+        );
+        assert_eq!(ExitStatus::NotFound.code(), 127);
+        assert_eq!(ExitStatus::NotExecutable.code(), 126);
+        assert_eq!(ExitStatus::BuiltinError.code(), 1);
+        assert!(ExitStatus::SUCCESS.success());
+        assert!(!ExitStatus::Signaled(libc::SIGTERM).success());
+        assert_eq!(ExitStatus::NotFound.to_string(), "127");
+    }
+
+    #[test]
+    fn decodes_wait_statuses() {
+        //Encoded the way Linux reports them to waitpid()
+        assert_eq!(ExitStatus::from_wait_status(2 << 8), ExitStatus::Code(2));
+        assert_eq!(
+            ExitStatus::from_wait_status(libc::SIGKILL),
+            ExitStatus::Signaled(libc::SIGKILL)
+        );
+        assert_eq!(
+            ExitStatus::from_wait_status((libc::SIGTSTP << 8) | 0x7f),
+            ExitStatus::Stopped(libc::SIGTSTP)
+        );
+    }
+
+    #[test]
+    fn names_and_describes_signals() {
+        assert_eq!(
+            ExitStatus::Signaled(libc::SIGKILL).signal_name().as_deref(),
+            Some("SIGKILL")
+        );
+        assert_eq!(
+            ExitStatus::Stopped(libc::SIGTSTP).signal_name().as_deref(),
+            Some("SIGTSTP")
+        );
+        assert_eq!(
+            ExitStatus::Signaled(64).signal_name().as_deref(),
+            Some("SIG64")
+        );
+        assert_eq!(ExitStatus::Code(1).signal_name(), None);
+        assert_eq!(
+            ExitStatus::Signaled(libc::SIGSEGV).describe(),
+            "Segmentation fault"
+        );
+        assert_eq!(ExitStatus::Stopped(libc::SIGTSTP).describe(), "Stopped");
+        assert_eq!(ExitStatus::Signaled(64).describe(), "Signal 64");
+        assert_eq!(ExitStatus::SUCCESS.describe(), "Done");
+        assert_eq!(ExitStatus::NotFound.describe(), "Exit 127");
+    }
+}
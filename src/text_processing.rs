@@ -1,161 +1,867 @@
 use std::error::Error;
 
 use crate::{
-    instruction::{Instruction, StdoutTo},
+    arithmetic,
+    ast::{List, Parameter, ParameterOperation, Word, WordPart},
+    glob,
+    lexer::{self, IncompleteInput},
+    parser,
+    session::Session,
     utils,
 };
 
 /*
-Parses the user's input and returns a vector holding one or more Instruction(s).
-Multiple commands are separated by pipes as the user enters their input,
-resulting in multiple Instructions.
+Parses the user's input into a List of pipelines.
+The input is split into tokens by the lexer first, which are then
+assembled into a syntax tree by the parser.
 */
-pub fn parse_input(input: &str) -> Result<Vec<Instruction>, Box<dyn Error>> {
-    let mut all_instructions: Vec<Instruction> = Vec::new();
-
-    let mut instruction = Instruction::new();
-
-    let mut quote_opened = false;
-    let mut quote_type = ' ';
-    //Keep track of the number of subcommands open in current iteration
-    let mut subs_opened = 0;
-    //The temporary elements being built and eventually pushed into instruction.command
-    let mut current_element = String::new();
-    let mut temp_string = String::new();
-    //Whether or not temp_string should actually be pushed into current_element after each iteration
-    let mut push_allowed: bool;
-    let chars: Vec<char> = input.chars().collect();
-    for (i, c_ref) in chars.iter().enumerate() {
-        if !instruction.read_from_pipe {
-            if let Some(prev_instruction) = all_instructions.last() {
-                if let StdoutTo::Pipe = prev_instruction.stdout_to {
-                    instruction.read_from_pipe = true
-                }
-            }
+pub fn parse_input(input: &str) -> Result<List, Box<dyn Error>> {
+    let tokens = lexer::tokenize(input)?;
+    parser::parse(tokens)
+}
+
+//Whether the input ends before all of its constructs (e.g. here-documents) are complete
+pub fn is_incomplete(input: &str) -> bool {
+    match parse_input(input) {
+        Err(e) => e.is::<IncompleteInput>(),
+        Ok(_) => false,
+    }
+}
+
+//Characters separating fields if IFS isn't set
+const DEFAULT_IFS: &str = " \t\n";
+
+/*
+Turns a word of the syntax tree into the fields handed to a command:
+braces are expanded into multiple words first (e.g. file{1,2}), a leading ~ is replaced by the home directory, parameters and subcommands
+are replaced by their values and quotes are removed. The values of
+unquoted parameters are split into separate fields at the characters of IFS.
+Finally, fields containing unquoted wildcards are replaced by the matching paths.
+*/
+pub fn expand_word(word: &Word, session: &mut Session) -> Result<Vec<String>, Box<dyn Error>> {
+    let ifs = session
+        .get_var("IFS")
+        .unwrap_or_else(|| DEFAULT_IFS.to_string());
+    let mut fields = Fields::new(ifs);
+    fields.escape_quoted = true;
+    for word in expand_braces(word) {
+        expand_parts(&word, session, false, &mut fields)?;
+        fields.end_field();
+    }
+
+    let options = glob::PathOptions {
+        dotglob: session.option_enabled("dotglob"),
+        globstar: session.option_enabled("globstar"),
+    };
+    let mut expanded = Vec::new();
+    for pattern in fields.finish() {
+        if !glob::has_wildcards(&pattern) {
+            expanded.push(glob::unescape(&pattern));
+            continue;
         }
-        push_allowed = true;
-        let c = *c_ref;
-        if c == '"' || c == '\'' {
-            push_allowed = false;
-            if !quote_opened {
-                quote_type = c;
-                quote_opened = true;
-            } else if quote_opened && c == quote_type {
-                quote_opened = false;
-            } else {
-                push_allowed = true;
-            }
-        } else if !quote_opened {
-            temp_string.clear();
-            if c.is_whitespace() && subs_opened == 0 {
-                push_allowed = false;
-                if current_element.len() != 0 {
-                    if let StdoutTo::File(_) = instruction.stdout_to {
-                        instruction.filename = current_element;
-                    } else {
-                        instruction.command.push(current_element);
-                    }
-                    current_element = String::new();
-                }
-            } else if c == '|' && subs_opened == 0 {
-                push_allowed = false;
-                if let StdoutTo::Stdout = instruction.stdout_to {
-                    instruction.stdout_to = StdoutTo::Pipe;
-                }
-                if current_element.len() != 0 {
-                    if let StdoutTo::File(_) = instruction.stdout_to {
-                        instruction.filename = current_element;
-                    } else {
-                        instruction.command.push(current_element);
+        let paths = glob::expand_path(&pattern, &options);
+        if !paths.is_empty() {
+            expanded.extend(paths);
+        } else if session.option_enabled("failglob") {
+            return Err(format!("No match: {}", glob::unescape(&pattern)).into());
+        } else if !session.option_enabled("nullglob") {
+            //Patterns without any matches are left as they are
+            expanded.push(glob::unescape(&pattern));
+        }
+    }
+    Ok(expanded)
+}
+
+/*
+Same as expand_word(), but the result isn't split into fields. Used wherever
+a single string is expected, e.g. for filenames of redirections and assignments.
+*/
+pub fn expand_word_to_string(word: &Word, session: &mut Session) -> Result<String, Box<dyn Error>> {
+    let mut fields = Fields::new(String::new());
+    expand_parts(word, session, false, &mut fields)?;
+    Ok(fields.finish().join(" "))
+}
+
+/*
+Same as expand_word_to_string(), but for words used as patterns (e.g. in ${NAME%.txt}).
+Quoted parts are escaped, so that their special characters only match themselves.
+*/
+pub fn expand_word_to_pattern(
+    word: &Word,
+    session: &mut Session,
+) -> Result<String, Box<dyn Error>> {
+    let mut fields = Fields::new(String::new());
+    fields.escape_quoted = true;
+    expand_parts(word, session, false, &mut fields)?;
+    Ok(fields.finish().join(" "))
+}
+
+//A word broken up for brace expansion: characters of unquoted text, which may form braces, and any other parts
+#[derive(Clone)]
+enum BraceItem {
+    Char(char),
+    Part(WordPart),
+}
+
+/*
+Expands the braces of a word into multiple words, either holding each of
+the enclosed alternatives (a{b,c}d becomes abd acd) or each element of a range
+({1..10}, {a..z}, {01..10..2}). Braces can be nested, and quoted braces as well
+as ones without a comma or range are left as they are.
+*/
+fn expand_braces(word: &Word) -> Vec<Word> {
+    let items = word
+        .iter()
+        .flat_map(|part| match part {
+            WordPart::Literal(text) => text.chars().map(BraceItem::Char).collect(),
+            part => vec![BraceItem::Part(part.clone())],
+        })
+        .collect();
+    expand_brace_items(items)
+        .into_iter()
+        .map(|items| {
+            let mut word = Vec::new();
+            let mut literal = String::new();
+            for item in items {
+                match item {
+                    BraceItem::Char(c) => literal.push(c),
+                    BraceItem::Part(part) => {
+                        lexer::push_literal(&mut word, &mut literal);
+                        word.push(part);
                     }
-                    current_element = String::new();
                 }
-                if instruction.command.len() != 0 {
-                    all_instructions.push(instruction);
-                    instruction = Instruction::new();
+            }
+            lexer::push_literal(&mut word, &mut literal);
+            word
+        })
+        .collect()
+}
+
+fn expand_brace_items(items: Vec<BraceItem>) -> Vec<Vec<BraceItem>> {
+    for start in 0..items.len() {
+        if !matches!(items[start], BraceItem::Char('{')) {
+            continue;
+        }
+        let Some((end, commas)) = find_closing_brace(&items, start) else {
+            continue;
+        };
+        let alternatives: Vec<Vec<BraceItem>> = if commas.is_empty() {
+            match brace_range(&items[start + 1..end]) {
+                Some(range) => range
+                    .into_iter()
+                    .map(|element| element.chars().map(BraceItem::Char).collect())
+                    .collect(),
+                None => continue,
+            }
+        } else {
+            let mut bounds = vec![start];
+            bounds.extend(commas);
+            bounds.push(end);
+            bounds
+                .windows(2)
+                .map(|bounds| items[bounds[0] + 1..bounds[1]].to_vec())
+                .collect()
+        };
+
+        let mut words = Vec::new();
+        for alternative in alternatives {
+            let mut word = items[..start].to_vec();
+            word.extend(alternative);
+            word.extend_from_slice(&items[end + 1..]);
+            //The alternative itself and the rest of the word may contain further braces
+            words.extend(expand_brace_items(word));
+        }
+        return words;
+    }
+    vec![items]
+}
+
+//Finds the '}' matching the '{' at start, along with the positions of the commas directly within them
+fn find_closing_brace(items: &[BraceItem], start: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (i, item) in items.iter().enumerate().skip(start) {
+        match item {
+            BraceItem::Char('{') => depth += 1,
+            BraceItem::Char('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((i, commas));
                 }
-            } else if c == '~' {
-                let home_pathbuf = utils::home_dir()?;
-                temp_string = format!("{}", home_pathbuf.display());
-            } else if c == '>' {
-                if subs_opened > 0 {
-                    push_allowed = true;
+            }
+            BraceItem::Char(',') if depth == 1 => commas.push(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+//Returns the elements of a range like 1..10, a..e or 0..100..10, or None if items don't form one
+fn brace_range(items: &[BraceItem]) -> Option<Vec<String>> {
+    let mut text = String::new();
+    for item in items {
+        match item {
+            BraceItem::Char(c) => text.push(*c),
+            BraceItem::Part(_) => return None,
+        }
+    }
+    let bounds: Vec<&str> = text.split("..").collect();
+    let (first, last, step) = match bounds[..] {
+        [first, last] => (first, last, 1),
+        [first, last, step] => (first, last, step.parse::<i64>().ok()?.unsigned_abs().max(1)),
+        _ => return None,
+    };
+
+    if let (Ok(start), Ok(end)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        //Leading zeros pad all elements to the same width, e.g. {01..10}
+        let padded = |bound: &str| {
+            bound.trim_start_matches('-').len() > 1
+                && bound.trim_start_matches('-').starts_with('0')
+        };
+        let width = if padded(first) || padded(last) {
+            first.len().max(last.len())
+        } else {
+            0
+        };
+        let range = range_steps(start, end, step)
+            .map(|n| format!("{:0width$}", n, width = width))
+            .collect();
+        return Some(range);
+    }
+
+    let mut first_chars = first.chars();
+    let mut last_chars = last.chars();
+    match (
+        first_chars.next(),
+        first_chars.next(),
+        last_chars.next(),
+        last_chars.next(),
+    ) {
+        (Some(start), None, Some(end), None)
+            if start.is_ascii_alphabetic() && end.is_ascii_alphabetic() =>
+        {
+            let range = range_steps(start as i64, end as i64, step)
+                .map(|c| (c as u8 as char).to_string())
+                .collect();
+            Some(range)
+        }
+        _ => None,
+    }
+}
+
+//Counts from start to end (in either direction) in steps of step
+fn range_steps(start: i64, end: i64, step: u64) -> impl Iterator<Item = i64> {
+    let count = start.abs_diff(end) / step + 1;
+    let step = if start <= end {
+        step as i64
+    } else {
+        -(step as i64)
+    };
+    (0..count as i64).map(move |i| start + i * step)
+}
+
+fn expand_parts(
+    parts: &[WordPart],
+    session: &mut Session,
+    quoted: bool, //Whether the parts are enclosed in double quotes
+    fields: &mut Fields,
+) -> Result<(), Box<dyn Error>> {
+    for (i, part) in parts.iter().enumerate() {
+        match part {
+            WordPart::Literal(text) => {
+                if !quoted && i == 0 && (text == "~" || text.starts_with("~/")) {
+                    fields.push_quoted(&utils::home_dir()?.display().to_string());
+                    fields.push(&text[1..]);
+                } else if quoted {
+                    fields.push_quoted(text);
                 } else {
-                    push_allowed = false;
-                    if let StdoutTo::File(_) = instruction.stdout_to {
-                        continue;
-                    }
-                    //Overwrite file
-                    let mut write_mode = 'o';
-                    if let Some(next_char) = chars.get(i + 1) {
-                        if *next_char == c {
-                            //Append to file
-                            write_mode = 'a';
-                        }
-                    }
-                    instruction.stdout_to = StdoutTo::File(write_mode);
+                    fields.push(text);
                 }
-            } else if c == '$' {
-                if let Some('{') = chars.get(i + 1) {
-                    subs_opened += 1;
+            }
+            WordPart::SingleQuoted(text) => fields.push_quoted(text),
+            WordPart::DoubleQuoted(parts) => {
+                //"$@" without any positional parameters results in no field at all
+                let only_all_params =
+                    matches!(&parts[..], [WordPart::Parameter(p)] if p.name == "@");
+                if !only_all_params || !session.positional_parameters().is_empty() {
+                    fields.push("");
                 }
-                if subs_opened > 1 {
-                    temp_string.push_str("$");
-                } else {
-                    push_allowed = false;
+                expand_parts(parts, session, true, fields)?;
+            }
+            WordPart::Substitution(source) => {
+                let output = substitute_command(source, session)?;
+                //Trailing newlines are removed, but any others are kept
+                fields.push_value(output.trim_end_matches('\n'), quoted);
+            }
+            WordPart::LegacySubstitution(source) => {
+                if !session.option_enabled("legacy_substitution") {
+                    return Err(format!(
+                        "Bad substitution: ${{{}}}. Use $({}) to substitute a command's output.",
+                        source, source
+                    )
+                    .into());
                 }
-            } else if subs_opened > 0 {
-                if c == '{' {
-                    if subs_opened > 1 {
-                        temp_string.push_str("{");
-                    } else {
-                        push_allowed = false;
-                    }
-                } else if c == '}' {
-                    subs_opened -= 1;
-                    if subs_opened == 0 {
-                        push_allowed = false;
-                        instruction.command.push(current_element);
-                        instruction
-                            .subcommand_indices
-                            .push(instruction.command.len() - 1);
-
-                        current_element = String::new();
+                //Legacy substitutions always result in a single field
+                fields.push_quoted(substitute_command(source, session)?.trim());
+            }
+            WordPart::Parameter(parameter) => {
+                expand_parameter(parameter, session, quoted, fields)?;
+            }
+            WordPart::Arithmetic(parts) => {
+                let expression = expand_word_to_string(parts, session)?;
+                let value = arithmetic::evaluate(&expression, session)?;
+                fields.push_value(&value.to_string(), quoted);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn expand_parameter(
+    parameter: &Parameter,
+    session: &mut Session,
+    quoted: bool,
+    fields: &mut Fields,
+) -> Result<(), Box<dyn Error>> {
+    let Some(operation) = &parameter.operation else {
+        return expand_plain_parameter(parameter, session, quoted, fields);
+    };
+    let name = parameter.name.as_str();
+    let values = lookup_parameter(parameter, session)?;
+    let value = match &values {
+        Values::Single(value) => value.clone(),
+        Values::List { values, .. } if values.is_empty() => None,
+        Values::List { values, .. } => Some(values.join(" ")),
+    };
+    //Whether the value counts as set for the operators with an optional ':'
+    let is_set = |check_null: bool| match &value {
+        Some(value) => !check_null || !value.is_empty(),
+        None => false,
+    };
+
+    let result = match operation {
+        ParameterOperation::UseDefault { word, check_null } => {
+            if !is_set(*check_null) {
+                return expand_parts(word, session, quoted, fields);
+            }
+            value.unwrap_or_default()
+        }
+        ParameterOperation::AssignDefault { word, check_null } => {
+            if is_set(*check_null) {
+                value.unwrap_or_default()
+            } else if !lexer::is_variable_name(name) {
+                return Err(format!("${}: cannot assign in this way", name).into());
+            } else {
+                let default = expand_word_to_string(word, session)?;
+                session.set_var(name, default.clone());
+                default
+            }
+        }
+        ParameterOperation::ErrorIfUnset { word, check_null } => {
+            if !is_set(*check_null) {
+                let message = expand_word_to_string(word, session)?;
+                let message = if message.is_empty() {
+                    "parameter null or not set".to_string()
+                } else {
+                    message
+                };
+                return Err(format!("{}: {}", name, message).into());
+            }
+            value.unwrap_or_default()
+        }
+        ParameterOperation::UseAlternative { word, check_null } => {
+            if is_set(*check_null) {
+                return expand_parts(word, session, quoted, fields);
+            }
+            String::new()
+        }
+        ParameterOperation::Length => match values {
+            //${#@} is the number of positional parameters rather than the length of their values,
+            //just like ${#NAME[@]} is the number of elements
+            Values::List { values, .. } => values.len().to_string(),
+            Values::Single(value) => value.unwrap_or_default().chars().count().to_string(),
+        },
+        ParameterOperation::RemovePrefix { pattern, longest } => {
+            let pattern = expand_word_to_pattern(pattern, session)?;
+            let value = value.unwrap_or_default();
+            remove_prefix(&value, &pattern, *longest).to_string()
+        }
+        ParameterOperation::RemoveSuffix { pattern, longest } => {
+            let pattern = expand_word_to_pattern(pattern, session)?;
+            let value = value.unwrap_or_default();
+            remove_suffix(&value, &pattern, *longest).to_string()
+        }
+        ParameterOperation::Replace {
+            pattern,
+            replacement,
+            all,
+        } => {
+            let pattern = expand_word_to_pattern(pattern, session)?;
+            let replacement = expand_word_to_string(replacement, session)?;
+            replace_matches(&value.unwrap_or_default(), &pattern, &replacement, *all)
+        }
+        ParameterOperation::Substring { offset, length } => {
+            let value: Vec<char> = value.unwrap_or_default().chars().collect();
+            let offset = parse_number(&expand_word_to_string(offset, session)?)?;
+            //Negative offsets count from the end of the value
+            let start = if offset < 0 {
+                value.len().saturating_sub(offset.unsigned_abs() as usize)
+            } else {
+                (offset as usize).min(value.len())
+            };
+            let end = match length {
+                Some(length) => {
+                    let length = parse_number(&expand_word_to_string(length, session)?)?;
+                    //A negative length is an offset from the end as well
+                    if length < 0 {
+                        value.len().saturating_sub(length.unsigned_abs() as usize)
                     } else {
-                        temp_string.push_str("}");
+                        start.saturating_add(length as usize).min(value.len())
                     }
                 }
+                None => value.len(),
+            };
+            if end < start {
+                return Err(format!("{}: substring expression < 0", name).into());
             }
+            value[start..end].iter().collect()
         }
+    };
+    fields.push_value(&result, quoted);
+    Ok(())
+}
 
-        if push_allowed {
-            if temp_string.is_empty() {
-                //If none of the above conditions were met, simply
-                //push the character
-                current_element.push(c);
-            } else {
-                current_element.push_str(&temp_string);
+fn expand_plain_parameter(
+    parameter: &Parameter,
+    session: &mut Session,
+    quoted: bool,
+    fields: &mut Fields,
+) -> Result<(), Box<dyn Error>> {
+    let name = parameter.name.as_str();
+    match lookup_parameter(parameter, session)? {
+        //"$@" results in one field for each value, "$*" joins them into a single field
+        Values::List { values, joined } => {
+            if quoted && joined {
+                let separator = fields.ifs.chars().next().unwrap_or(' ');
+                fields.push_quoted(&values.join(&separator.to_string()));
+                return Ok(());
             }
-            if i == input.len() - 1 {
-                if let StdoutTo::File(_) = instruction.stdout_to {
-                    instruction.filename = current_element.clone();
-                } else {
-                    instruction.command.push(current_element.clone());
+            for (i, value) in values.iter().enumerate() {
+                if i != 0 {
+                    fields.end_field();
                 }
-                current_element.clear();
+                fields.push_value(value, quoted);
+            }
+        }
+        Values::Single(Some(value)) => fields.push_value(&value, quoted),
+        //${whoami} used to be the only way of substituting a command's output.
+        //With the legacy option, it still is as long as there's no variable of that name.
+        Values::Single(None)
+            if parameter.braced
+                && parameter.index.is_none()
+                && lexer::is_variable_name(name)
+                && session.option_enabled("legacy_substitution") =>
+        {
+            fields.push_quoted(substitute_command(name, session)?.trim());
+        }
+        Values::Single(None) => {}
+    }
+    Ok(())
+}
+
+//What a parameter refers to
+enum Values {
+    Single(Option<String>), //The value of a variable, None if it's unset
+    List { values: Vec<String>, joined: bool }, //$@ and ${NAME[@]}, or $* and ${NAME[*]} if joined
+}
+
+/*
+Looks up the value(s) of a parameter: all positional parameters for $@ and $*, and for
+a subscript either all elements of the array or the one at the index it evaluates to.
+Negative indices count from the end. Variables which aren't arrays have a single element.
+*/
+fn lookup_parameter(
+    parameter: &Parameter,
+    session: &mut Session,
+) -> Result<Values, Box<dyn Error>> {
+    let name = parameter.name.as_str();
+    let Some(index) = &parameter.index else {
+        return Ok(match name {
+            "@" | "*" => Values::List {
+                values: session.positional_parameters().to_vec(),
+                joined: name == "*",
+            },
+            name => Values::Single(session.get_var(name)),
+        });
+    };
+    let elements = session.get_array(name).unwrap_or_default();
+    let index = expand_word_to_string(index, session)?;
+    if index == "@" || index == "*" {
+        let joined = index == "*";
+        return Ok(Values::List {
+            values: elements,
+            joined,
+        });
+    }
+    let index = arithmetic::evaluate(&index, session)?;
+    let index = if index < 0 {
+        elements.len() as i64 + index
+    } else {
+        index
+    };
+    let element = usize::try_from(index).ok().and_then(|i| elements.get(i));
+    Ok(Values::Single(element.cloned()))
+}
+
+//Removes the shortest (or longest) beginning of value matching pattern
+fn remove_prefix<'a>(value: &'a str, pattern: &str, longest: bool) -> &'a str {
+    let mut ends: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    ends.push(value.len());
+    if longest {
+        ends.reverse();
+    }
+    match ends
+        .into_iter()
+        .find(|end| glob::matches(pattern, &value[..*end]))
+    {
+        Some(end) => &value[end..],
+        None => value,
+    }
+}
+
+//Removes the shortest (or longest) ending of value matching pattern
+fn remove_suffix<'a>(value: &'a str, pattern: &str, longest: bool) -> &'a str {
+    let mut starts: Vec<usize> = value.char_indices().map(|(i, _)| i).collect();
+    starts.push(value.len());
+    if !longest {
+        starts.reverse();
+    }
+    match starts
+        .into_iter()
+        .find(|start| glob::matches(pattern, &value[*start..]))
+    {
+        Some(start) => &value[..start],
+        None => value,
+    }
+}
+
+//Replaces the first (or every) longest non-empty match of pattern within value
+fn replace_matches(value: &str, pattern: &str, replacement: &str, all: bool) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut result = String::new();
+    let mut i = 0;
+    let mut replaced = false;
+    while i < chars.len() {
+        let end = if replaced && !all {
+            None
+        } else {
+            (i + 1..=chars.len())
+                .rev()
+                .find(|end| glob::matches(pattern, &chars[i..*end].iter().collect::<String>()))
+        };
+        match end {
+            Some(end) => {
+                result.push_str(replacement);
+                replaced = true;
+                i = end;
+            }
+            None => {
+                result.push(chars[i]);
+                i += 1;
             }
         }
     }
+    result
+}
+
+fn parse_number(text: &str) -> Result<i64, Box<dyn Error>> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{}: invalid number", text.trim()).into())
+}
+
+//Runs source as a subcommand and returns its stdout
+fn substitute_command(source: &str, session: &mut Session) -> Result<String, Box<dyn Error>> {
+    //Unwrap() will not panic, since output is always Some()
+    //if as_subcommand is set to true.
+    Ok(session.execute_input(source, true)?.unwrap())
+}
+
+//Collects the fields a word expands to
+struct Fields {
+    ifs: String, //Characters at which unquoted values are split, none at all if empty
+    finished: Vec<String>,
+    current: String,
+    started: bool,       //Whether current is a field, even if it's empty (e.g. for "")
+    escape_quoted: bool, //Whether quoted text is escaped, for words that are used as patterns or globbed
+}
+
+impl Fields {
+    fn new(ifs: String) -> Self {
+        Self {
+            ifs,
+            finished: Vec::new(),
+            current: String::new(),
+            started: false,
+            escape_quoted: false,
+        }
+    }
 
-    if !current_element.is_empty() {
-        instruction.command.push(current_element);
+    //Appends text to the current field without splitting it
+    fn push(&mut self, text: &str) {
+        self.current.push_str(text);
+        self.started = true;
     }
 
-    //The last command in user's input is followed by whitespace and needs
-    //to be added here.
-    if instruction.command.len() != 0 {
-        all_instructions.push(instruction);
+    //Appends quoted text, which is never split and only ever matches itself within patterns
+    fn push_quoted(&mut self, text: &str) {
+        if self.escape_quoted {
+            self.push(&glob::escape(text));
+        } else {
+            self.push(text);
+        }
     }
 
-    Ok(all_instructions)
+    //Appends the value of an expansion, which is only split if it isn't quoted
+    fn push_value(&mut self, text: &str, quoted: bool) {
+        if quoted {
+            self.push_quoted(text);
+        } else {
+            self.push_split(text);
+        }
+    }
+
+    /*
+    Appends text to the current field, starting a new field at each character of IFS.
+    Consecutive whitespace counts as a single separator.
+    */
+    fn push_split(&mut self, text: &str) {
+        for c in text.chars() {
+            //Wildcards of unquoted values stay special, but backslashes don't
+            if c == '\\' && self.escape_quoted {
+                self.current.push('\\');
+            }
+            if !self.ifs.contains(c) {
+                self.current.push(c);
+                self.started = true;
+            } else if c.is_whitespace() {
+                self.end_field();
+            } else {
+                self.started = true;
+                self.end_field();
+            }
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.started {
+            self.finished.push(std::mem::take(&mut self.current));
+            self.started = false;
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.end_field();
+        self.finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Token;
+
+    //The only word of text, as read by the lexer
+    fn word(text: &str) -> Word {
+        match &lexer::tokenize(text).unwrap()[..] {
+            [Token::Word(word)] => word.clone(),
+            tokens => panic!("{:?} isn't a single word", tokens),
+        }
+    }
+
+    fn braces(text: &str) -> Vec<Word> {
+        expand_braces(&word(text))
+    }
+
+    fn words(texts: &[&str]) -> Vec<Word> {
+        texts.iter().map(|text| word(text)).collect()
+    }
+
+    fn range(text: &str) -> Option<Vec<String>> {
+        let items: Vec<BraceItem> = text.chars().map(BraceItem::Char).collect();
+        brace_range(&items)
+    }
+
+    //The value of text as a single word, with v set to value
+    fn expand_with(text: &str, value: &str) -> Result<String, Box<dyn Error>> {
+        let mut session = Session::new();
+        session.set_var("v", value.to_string());
+        expand_word_to_string(&word(text), &mut session)
+    }
+
+    #[test]
+    fn removes_shortest_and_longest_matches() {
+        assert_eq!(remove_prefix("a.b.c", "*.", false), "b.c");
+        assert_eq!(remove_prefix("a.b.c", "*.", true), "c");
+        assert_eq!(remove_prefix("a.b.c", "x*", true), "a.b.c");
+        assert_eq!(remove_prefix("abc", "*", false), "abc");
+        assert_eq!(remove_prefix("abc", "*", true), "");
+        assert_eq!(remove_suffix("a.b.c", ".*", false), "a.b");
+        assert_eq!(remove_suffix("a.b.c", ".*", true), "a");
+        assert_eq!(remove_suffix("a.b.c", "*x", false), "a.b.c");
+        assert_eq!(remove_suffix("äöü.txt", ".txt", false), "äöü");
+        assert_eq!(
+            expand_with("${v%.*}", "archive.tar.gz").unwrap(),
+            "archive.tar"
+        );
+        assert_eq!(
+            expand_with("${v%%.*}", "archive.tar.gz").unwrap(),
+            "archive"
+        );
+        assert_eq!(
+            expand_with("${v#*/}", "/usr/local/bin").unwrap(),
+            "usr/local/bin"
+        );
+        assert_eq!(expand_with("${v##*/}", "/usr/local/bin").unwrap(), "bin");
+    }
+
+    #[test]
+    fn replaces_first_or_every_match() {
+        assert_eq!(replace_matches("hello", "l", "L", false), "heLlo");
+        assert_eq!(replace_matches("hello", "l", "L", true), "heLLo");
+        //Matches are as long as possible, but never empty
+        assert_eq!(replace_matches("hello", "l*", "_", true), "he_");
+        assert_eq!(replace_matches("hello", "*", "_", false), "_");
+        assert_eq!(replace_matches("hello", "x", "_", true), "hello");
+        assert_eq!(replace_matches("", "*", "_", true), "");
+        assert_eq!(
+            expand_with("${v//l/L}", "hello world").unwrap(),
+            "heLLo worLd"
+        );
+        assert_eq!(expand_with("${v/o/}", "foo").unwrap(), "fo");
+    }
+
+    #[test]
+    fn takes_substrings() {
+        assert_eq!(expand_with("${v:2}", "abcdef").unwrap(), "cdef");
+        assert_eq!(expand_with("${v:1:3}", "abcdef").unwrap(), "bcd");
+        assert_eq!(expand_with("${v: -2}", "abcdef").unwrap(), "ef");
+        assert_eq!(expand_with("${v:1:-1}", "abcdef").unwrap(), "bcde");
+        assert_eq!(expand_with("${v: -3:-1}", "abcdef").unwrap(), "de");
+        //Lengths and offsets beyond the value are cut off
+        assert_eq!(expand_with("${v:2:100}", "abcdef").unwrap(), "cdef");
+        assert_eq!(expand_with("${v:10}", "abcdef").unwrap(), "");
+        assert_eq!(expand_with("${v: -10}", "abcdef").unwrap(), "abcdef");
+        assert_eq!(expand_with("${v:1:2}", "äöüß").unwrap(), "öü");
+        assert_eq!(
+            expand_with("${v:4:-3}", "abcdef").unwrap_err().to_string(),
+            "v: substring expression < 0"
+        );
+        assert!(expand_with("${v:x}", "abcdef").is_err());
+    }
+
+    #[test]
+    fn expands_alternatives() {
+        assert_eq!(braces("a{b,c}d"), words(&["abd", "acd"]));
+        assert_eq!(braces("{,x}"), [Word::new(), word("x")]);
+        assert_eq!(braces("{a,b}{1,2}"), words(&["a1", "a2", "b1", "b2"]));
+        assert_eq!(braces("x{a,{b,c}d}"), words(&["xa", "xbd", "xcd"]));
+        assert_eq!(braces("{a,{1..3}}"), words(&["a", "1", "2", "3"]));
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() {
+        for text in [
+            "{}",
+            "{a}",
+            "{a,b",
+            "a}b",
+            "{1..}",
+            "{a..1}",
+            "{1..2..x}",
+            "'{a,b}'",
+            "\\{a,b}",
+        ] {
+            assert_eq!(braces(text), [word(text)], "{}", text);
+        }
+        //Quoted commas don't separate alternatives
+        assert_eq!(braces("{a','b}"), [word("{a','b}")]);
+    }
+
+    #[test]
+    fn expands_next_to_parameters() {
+        assert_eq!(braces("{a,b}${VAR}"), words(&["a${VAR}", "b${VAR}"]));
+        assert_eq!(braces("${VAR}{1..2}"), words(&["${VAR}1", "${VAR}2"]));
+        assert_eq!(braces("\"$x\"{a,b}"), words(&["\"$x\"a", "\"$x\"b"]));
+        let mut session = Session::new();
+        session.set_var("brace_test", "v".to_string());
+        let fields = expand_word(&word("{a,b}${brace_test}"), &mut session).unwrap();
+        assert_eq!(fields, ["av", "bv"]);
+    }
+
+    #[test]
+    fn counts_ranges_in_both_directions() {
+        let strings = |texts: &[&str]| Some(texts.iter().map(|t| t.to_string()).collect());
+        assert_eq!(range("1..5"), strings(&["1", "2", "3", "4", "5"]));
+        assert_eq!(range("3..1"), strings(&["3", "2", "1"]));
+        assert_eq!(range("-2..1"), strings(&["-2", "-1", "0", "1"]));
+        assert_eq!(range("7..7"), strings(&["7"]));
+        assert_eq!(range("a..e"), strings(&["a", "b", "c", "d", "e"]));
+        assert_eq!(range("Z..X"), strings(&["Z", "Y", "X"]));
+        assert_eq!(range("ab..c"), None);
+        assert_eq!(range("1"), None);
+        assert_eq!(range("1..2..3..4"), None);
+    }
+
+    #[test]
+    fn steps_and_pads_ranges() {
+        let strings = |texts: &[&str]| Some(texts.iter().map(|t| t.to_string()).collect());
+        assert_eq!(range("0..10..5"), strings(&["0", "5", "10"]));
+        assert_eq!(range("1..10..4"), strings(&["1", "5", "9"]));
+        //The sign of the step doesn't matter, the direction comes from the bounds
+        assert_eq!(range("10..1..-3"), strings(&["10", "7", "4", "1"]));
+        assert_eq!(range("1..3..0"), strings(&["1", "2", "3"]));
+        assert_eq!(range("a..g..3"), strings(&["a", "d", "g"]));
+        assert_eq!(range("01..03"), strings(&["01", "02", "03"]));
+        assert_eq!(range("8..010"), strings(&["008", "009", "010"]));
+        assert_eq!(range("001..20..10"), strings(&["001", "011"]));
+        assert_eq!(range("-05..5..5"), strings(&["-05", "000", "005"]));
+    }
+
+    #[test]
+    fn tells_incomplete_from_invalid_input() {
+        for input in [
+            "echo 'abc\n",
+            "echo \"abc\n",
+            "echo $(ls\n",
+            "echo a \\\n",
+            "cat <<EOF\nabc\n",
+            "if true; then\n",
+            "ls |\n",
+            "f() {\n",
+        ] {
+            assert!(is_incomplete(input), "{:?}", input);
+        }
+        for input in [
+            "echo 'abc'\n",
+            "cat <<EOF\nabc\nEOF\n",
+            "fi\n",
+            "echo )\n",
+            "",
+        ] {
+            assert!(!is_incomplete(input), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn parses_continued_lines_as_one_command() {
+        assert_eq!(
+            parse_input("echo a \\\n b\n").unwrap(),
+            parse_input("echo a b\n").unwrap()
+        );
+        assert_eq!(
+            parse_input("if true\nthen echo a\nfi\n").unwrap(),
+            parse_input("if true; then echo a; fi").unwrap()
+        );
+    }
 }
@@ -1,7 +1,8 @@
 use std::{
     error::Error,
-    io::{self, Stdout, Write},
-    path::PathBuf,
+    fmt,
+    io::{self, BufRead, IsTerminal, Stdout, Write},
+    path::Path,
     time::Duration,
 };
 
@@ -13,7 +14,11 @@ use crossterm::{
     terminal::{self, Clear, ClearType},
 };
 
-use crate::utils;
+use crate::{
+    status::ExitStatus,
+    text_processing,
+    utils::{self, UnbufferedStdin},
+};
 
 /*
 For managing user input aspects, such as
@@ -37,8 +42,81 @@ impl Input {
 
     /*
     Displays the prompt and calls read_keys() to get the user's input, finally returns it as a String.
+    As long as the input is incomplete (e.g. a quote or here-document hasn't been closed, or the line
+    ends with a backslash or '|'), continuation_prompt is displayed and further lines are read.
+    All of them are stored as a single entry of the history. Ctrl-C discards the input read so far
+    and displays the prompt again.
+    */
+    pub fn prompt(
+        &mut self,
+        status: &ExitStatus,
+        prompt: &str,
+        continuation_prompt: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut prompt_color = Color::White;
+        let text_color = prompt_color;
+        if !status.success() {
+            prompt_color = Color::DarkRed;
+        }
+        'lines: loop {
+            execute!(
+                self.stdout,
+                SetAttribute(Attribute::Bold),
+                SetForegroundColor(prompt_color),
+                Print(prompt),
+                SetForegroundColor(text_color), //If prompt is read, user input should still be white
+                SetAttribute(Attribute::NormalIntensity)
+            )?;
+
+            let Some(mut input) = self.read_line()? else {
+                continue;
+            };
+            while text_processing::is_incomplete(&input) {
+                self.input.clear();
+                execute!(self.stdout, Print(continuation_prompt))?;
+                let Some(line) = self.read_line()? else {
+                    continue 'lines;
+                };
+                input.push('\n');
+                input.push_str(&line);
+            }
+            utils::write_history(&input)?;
+            return Ok(input);
+        }
+    }
+
+    /*
+    Same as prompt(), but for input which isn't a terminal (e.g. a pipe given to my_shell -i):
+    lines are taken as they are, without editing or history, and the prompts are written
+    to stderr. Returns None once the input has ended.
     */
-    pub fn prompt(&mut self, exit_code: &str, cwd: &PathBuf) -> Result<String, Box<dyn Error>> {
+    pub fn prompt_plain(
+        &mut self,
+        prompt: &str,
+        continuation_prompt: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let mut stdin = UnbufferedStdin::new();
+        eprint!("{}", prompt);
+        let mut input = String::new();
+        if stdin.read_line(&mut input)? == 0 {
+            return Ok(None);
+        }
+        input.truncate(input.trim_end_matches('\n').len());
+        while text_processing::is_incomplete(&input) {
+            eprint!("{}", continuation_prompt);
+            let mut line = String::new();
+            //What has been read so far is run (and fails) if the input ends in the middle of a command
+            if stdin.read_line(&mut line)? == 0 {
+                break;
+            }
+            input.push('\n');
+            input.push_str(line.trim_end_matches('\n'));
+        }
+        Ok(Some(input))
+    }
+
+    //The prompt used unless PS1 is set, the last component of cwd (e.g. "..src> ")
+    pub fn default_prompt(cwd: &Path) -> String {
         let mut prompt = String::new();
         //Trying to fetch the last component of cwd
         if let Some(os_str) = cwd.file_name() {
@@ -50,32 +128,22 @@ impl Input {
             //E.g. if cwd is the "/" dir
             prompt.push_str(&cwd.display().to_string());
         }
-        let mut prompt_color = Color::White;
-        let text_color = prompt_color;
-        if exit_code != "0" {
-            prompt_color = Color::DarkRed;
-        }
         prompt.push_str("> ");
-        execute!(
-            self.stdout,
-            SetAttribute(Attribute::Bold),
-            SetForegroundColor(prompt_color),
-            Print(prompt),
-            SetForegroundColor(text_color), //If prompt is read, user input should still be white
-            SetAttribute(Attribute::NormalIntensity)
-        )?;
+        prompt
+    }
 
-        terminal::enable_raw_mode()?;
+    //Reads a line with the terminal in raw mode, which is restored even if reading fails
+    fn read_line(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+        let _raw_mode = RawMode::enable()?;
         self.input_cursor = 0;
-
-        return Ok(self.read_keys()?);
+        self.read_keys()
     }
 
     /*
     Reads the user's keyboard input key by key and returns a string
-    representing the characters.
+    representing the characters, or None if Ctrl-C has been pressed.
     */
-    pub fn read_keys(&mut self) -> Result<String, Box<dyn Error>> {
+    pub fn read_keys(&mut self) -> Result<Option<String>, Box<dyn Error>> {
         //For navigating through history file using the
         //arrow up/down keys
         let mut history_pointer: Option<usize> = None;
@@ -85,8 +153,13 @@ impl Input {
                 if let Event::Key(key_ev) = ev {
                     match key_ev.code {
                         KeyCode::Char(ch) => {
-                            if key_ev.modifiers == KeyModifiers::CONTROL && ch == 'c' {
-                                //TODO
+                            if key_ev.modifiers == KeyModifiers::CONTROL {
+                                //Other control keys (e.g. Ctrl-Z) have no meaning at the prompt
+                                if ch == 'c' {
+                                    self.input.clear();
+                                    execute!(self.stdout, ResetColor, Print("^C\r\n"))?;
+                                    return Ok(None);
+                                }
                             } else {
                                 if self.input_cursor < self.input.len() {
                                     self.insert_char(ch)?;
@@ -102,83 +175,71 @@ impl Input {
                             let finished_input =
                                 self.input.iter().map(|c| c.to_string()).collect::<String>();
                             execute!(self.stdout, ResetColor, Print("\r\n"))?;
-                            utils::write_history(&finished_input)?;
-                            terminal::disable_raw_mode()?;
-                            return Ok(finished_input);
+                            return Ok(Some(finished_input));
                         }
-                        KeyCode::Left => {
-                            if self.input_cursor > 0 {
-                                self.input_cursor -= 1;
-                                execute!(self.stdout, MoveLeft(1))?;
-                            }
+                        KeyCode::Left if self.input_cursor > 0 => {
+                            self.input_cursor -= 1;
+                            execute!(self.stdout, MoveLeft(1))?;
                         }
-                        KeyCode::Right => {
-                            if self.input_cursor < self.input.len() {
-                                self.input_cursor += 1;
-                                execute!(self.stdout, MoveRight(1))?;
-                            }
+                        KeyCode::Right if self.input_cursor < self.input.len() => {
+                            self.input_cursor += 1;
+                            execute!(self.stdout, MoveRight(1))?;
                         }
-                        KeyCode::Backspace => {
-                            if self.input_cursor > 0 {
+                        KeyCode::Backspace if self.input_cursor > 0 => {
+                            queue!(
+                                self.stdout,
+                                MoveLeft(self.input_cursor as u16),
+                                Clear(ClearType::UntilNewLine),
+                            )?;
+                            if self.input_cursor < self.input.len() {
+                                self.input.remove(self.input_cursor - 1);
                                 queue!(
                                     self.stdout,
-                                    MoveLeft(self.input_cursor as u16),
-                                    Clear(ClearType::UntilNewLine),
+                                    Print(
+                                        self.input
+                                            .iter()
+                                            .map(|c| c.to_string())
+                                            .collect::<String>()
+                                    ),
+                                    MoveLeft((self.input.len() - self.input_cursor + 1) as u16)
+                                )?;
+                            } else {
+                                self.input.pop();
+                                queue!(
+                                    self.stdout,
+                                    Print(
+                                        self.input
+                                            .iter()
+                                            .map(|c| c.to_string())
+                                            .collect::<String>()
+                                    )
                                 )?;
-                                if self.input_cursor < self.input.len() {
-                                    self.input.remove(self.input_cursor - 1);
-                                    queue!(
-                                        self.stdout,
-                                        Print(
-                                            self.input
-                                                .iter()
-                                                .map(|c| c.to_string())
-                                                .collect::<String>()
-                                        ),
-                                        MoveLeft((self.input.len() - self.input_cursor + 1) as u16)
-                                    )?;
-                                } else {
-                                    self.input.pop();
-                                    queue!(
-                                        self.stdout,
-                                        Print(
-                                            self.input
-                                                .iter()
-                                                .map(|c| c.to_string())
-                                                .collect::<String>()
-                                        )
-                                    )?;
-                                }
-                                self.stdout.flush()?;
-                                self.input_cursor -= 1;
                             }
+                            self.stdout.flush()?;
+                            self.input_cursor -= 1;
                         }
                         //Navigating through history
                         KeyCode::Up | KeyCode::Down => {
                             //true => "Up" key was pressed. false => "Down" key pressed
-                            let up = if let KeyCode::Up = key_ev.code {
-                                true
-                            } else {
-                                false
-                            };
-                            let history = String::from_utf8(utils::read_history()?)?;
-                            let lines = history.lines().collect::<Vec<&str>>();
+                            let up = matches!(key_ev.code, KeyCode::Up);
+                            let entries = utils::read_history()?;
                             if let Some(val) = history_pointer {
                                 if up && val != 0 {
                                     history_pointer = Some(val - 1);
-                                } else if !up && val < lines.len() - 1 {
+                                } else if !up && val < entries.len() - 1 {
                                     history_pointer = Some(val + 1);
                                 }
                             } else {
-                                history_pointer = Some(lines.len() - 1);
+                                history_pointer = Some(entries.len() - 1);
                             }
-                            let next_command = lines[history_pointer.unwrap()];
+                            let next_command = &entries[history_pointer.unwrap()];
 
                             if !self.input.is_empty() {
                                 self.clear_prompt()?;
                             }
-                            //Write the new input into the prompt
-                            execute!(self.stdout, Print(next_command))?;
+                            //Write the new input into the prompt. Lines of entries
+                            //spanning several of them are started at the left edge.
+                            execute!(self.stdout, Print(next_command.replace('\n', "\r\n")))?;
 
                             self.input = next_command.chars().collect();
                             self.input_cursor = self.input.len();
@@ -228,10 +289,36 @@ impl Input {
     }
 }
 
+//Keeps the terminal in raw mode as long as it exists
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
 impl Output {
-    pub fn shell_error(stdout: &mut Stdout, err: Box<dyn Error>) {
-        execute!(
-            stdout,
+    /*
+    Displays an error of the shell itself on stderr, highlighted if it's a terminal.
+    Otherwise (e.g. a log file), the message is written as it is.
+    */
+    pub fn shell_error(err: Box<dyn Error>) {
+        let mut stderr = io::stderr();
+        if !stderr.is_terminal() {
+            //Nothing is left to report to if stderr is gone, e.g. a closed pipe
+            let _ = writeln!(stderr, "{}", err);
+            return;
+        }
+        let _ = execute!(
+            stderr,
             Print(format!(
                 "{}{}Shell error:{}\n{}\n",
                 SetForegroundColor(Color::DarkRed),
@@ -239,7 +326,12 @@ impl Output {
                 SetAttribute(Attribute::Reset),
                 err
             ))
-        )
-        .unwrap();
+        );
+    }
+
+    //Displays a message of the shell which isn't an error, e.g. that a job has finished
+    pub fn notice(msg: impl fmt::Display) {
+        //Unlike eprintln!(), doesn't panic if stderr is a closed pipe
+        let _ = writeln!(io::stderr(), "{}", msg);
     }
 }
@@ -1,32 +1,40 @@
-use home;
 use std::{
     error::Error,
     fs,
+    io::{self, BufRead, Read, Write},
     os::unix::fs::PermissionsExt,
     path::PathBuf,
-    io::Write
+    thread,
 };
 
+use os_pipe::PipeReader;
+
 pub fn home_dir() -> Result<PathBuf, String> {
     if let Some(pathbuf) = home::home_dir() {
-        return Ok(pathbuf);
+        Ok(pathbuf)
     } else {
         let msg = "Failed to retrieve home directory.".to_string();
-        return Err(msg);
+        Err(msg)
     }
 }
 
-/*
-Creates the config directory (~/.config/myshell) if nonexistent and returns the path
-*/
-pub fn config_dir() -> Result<PathBuf, Box<dyn Error>> {
+//Returns the path of the config directory (~/.config/myshell), which may not exist
+pub fn config_path() -> Result<PathBuf, String> {
     let mut config_dir = home_dir()?;
 
     for element in [".config", "myshell"] {
         config_dir.push(element);
     }
+    Ok(config_dir)
+}
+
+/*
+Creates the config directory (~/.config/myshell) if nonexistent and returns the path
+*/
+pub fn config_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let config_dir = config_path()?;
     if !config_dir.exists() {
-        fs::create_dir(&config_dir)?;
+        fs::create_dir_all(&config_dir)?;
     }
 
     Ok(config_dir)
@@ -34,7 +42,7 @@ pub fn config_dir() -> Result<PathBuf, Box<dyn Error>> {
 
 /*
 (Creates and) opens and returns a file with options according to the specified writing mode.
-    */ 
+    */
 pub fn open_file(filename: &str, mode: char) -> Result<fs::File, Box<dyn Error>> {
     let pathbuf = PathBuf::from(filename);
     if pathbuf.is_dir() {
@@ -51,11 +59,76 @@ pub fn open_file(filename: &str, mode: char) -> Result<fs::File, Box<dyn Error>>
     Ok(file_opts.open(pathbuf)?)
 }
 
+/*
+Creates a pipe and writes contents into it, returning the reading end.
+Writing happens on a separate thread, so that contents larger than
+the pipe's buffer don't block until somebody reads them.
+*/
+pub fn pipe_with_contents(contents: String) -> io::Result<PipeReader> {
+    let (reader, mut writer) = os_pipe::pipe()?;
+    thread::spawn(move || writer.write_all(contents.as_bytes()));
+    Ok(reader)
+}
+
+/*
+Reads stdin one byte at a time, so that nothing following the current line is read ahead.
+If commands come from a pipe, the rest of it is the input of the programs they run
+(e.g. of cat in 'printf "cat\nhello" | my_shell').
+*/
+pub struct UnbufferedStdin {
+    byte: [u8; 1],
+    filled: bool, //Whether byte has been read, but not consumed yet
+}
+
+impl UnbufferedStdin {
+    pub fn new() -> Self {
+        UnbufferedStdin {
+            byte: [0],
+            filled: false,
+        }
+    }
+}
+
+impl Read for UnbufferedStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl BufRead for UnbufferedStdin {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while !self.filled {
+            //Safety: byte is a valid buffer of one byte
+            match unsafe { libc::read(libc::STDIN_FILENO, self.byte.as_mut_ptr().cast(), 1) } {
+                0 => return Ok(&[]),
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                _ => self.filled = true,
+            }
+        }
+        Ok(&self.byte)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if amount > 0 {
+            self.filled = false;
+        }
+    }
+}
+
 /*
 Writes the user's input to history file located at the path <config_dir>/history
 */
 pub fn write_history(input: &str) -> Result<(), Box<dyn Error>> {
-    if input.len() == 0 {
+    if input.is_empty() {
         return Ok(());
     }
 
@@ -63,18 +136,56 @@ pub fn write_history(input: &str) -> Result<(), Box<dyn Error>> {
     histfile_path.push("history");
     if let Some(path) = histfile_path.to_str() {
         let mut file = open_file(path, 'a')?;
-        file.write(format!("{}\n", input).as_bytes())?;
+        file.write_all(format!("{}\n", escape_history_entry(input)).as_bytes())?;
     }
     Ok(())
 }
 
 /*
-Returns the contents of the history file as bytes.
+Returns the entries of the history file, oldest first.
 */
-pub fn read_history() -> Result<Vec<u8>, Box<dyn Error>> {
+pub fn read_history() -> Result<Vec<String>, Box<dyn Error>> {
     let mut histfile_path = config_dir()?;
     histfile_path.push("history");
-    Ok(fs::read(histfile_path)?)
+    let history = String::from_utf8(fs::read(histfile_path)?)?;
+    Ok(history.lines().map(unescape_history_entry).collect())
+}
+
+/*
+Entries spanning multiple lines are stored on a single one, marked by a leading
+MULTILINE_MARKER, with newlines written as \n and backslashes as \\. Other entries
+are stored as they are, like in files written before multi-line entries existed.
+*/
+const MULTILINE_MARKER: char = '\u{1e}'; //ASCII record separator, which the prompt doesn't let users type
+
+fn escape_history_entry(entry: &str) -> String {
+    if !entry.contains('\n') {
+        return entry.to_string();
+    }
+    let escaped = entry.replace('\\', "\\\\").replace('\n', "\\n");
+    format!("{}{}", MULTILINE_MARKER, escaped)
+}
+
+fn unescape_history_entry(line: &str) -> String {
+    let Some(line) = line.strip_prefix(MULTILINE_MARKER) else {
+        return line.to_string();
+    };
+    let mut entry = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            _ => entry.push(c),
+        }
+    }
+    entry
 }
 
 //TODO
@@ -110,4 +221,32 @@ pub fn bin_dir_contents() -> Result<Vec<String>, Box<dyn Error>> {
     }
 
     Ok(contents)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_entries_round_trip() {
+        for entry in [
+            "echo hi",
+            "printf 'a\\nb'",
+            "for i in 1 2\ndo echo $i\ndone",
+            "echo 'a\\nb\nc\\'\n",
+            "\\\\n\n",
+        ] {
+            let line = escape_history_entry(entry);
+            assert!(!line.contains('\n'), "{:?}", line);
+            assert_eq!(unescape_history_entry(&line), entry);
+        }
+    }
+
+    #[test]
+    fn single_line_entries_are_stored_as_they_are() {
+        //Such as the ones of files written before multi-line entries were supported
+        assert_eq!(escape_history_entry("printf 'a\\nb'"), "printf 'a\\nb'");
+        assert_eq!(unescape_history_entry("printf 'a\\nb'"), "printf 'a\\nb'");
+        assert_eq!(unescape_history_entry("echo \\\\"), "echo \\\\");
+    }
+}